@@ -0,0 +1,102 @@
+use crate::error::{EvalError, Span};
+use crate::format::NumberFormat;
+use crate::value::real;
+use crate::{Op, Value};
+use num_complex::Complex64;
+use num_traits::{ToPrimitive, Zero};
+
+/// Wraps a computed complex number, rejecting parts that aren't finite.
+/// Results with no imaginary part become plain reals, so `1i * 1i` is `-1`.
+pub(crate) fn complex(val: Complex64, span: Span) -> Result<Value, EvalError> {
+    let re = real(val.re, span)?;
+    if val.im == 0.0 {
+        return Ok(re);
+    }
+    real(val.im, span)?;
+    Ok(Value::Complex(val))
+}
+
+/// Applies `op` where either side is complex. Complex numbers have no
+/// order, so they can be compared for equality but not with `<` or `%`.
+pub(crate) fn binary_op(lhs: Value, op: &Op, rhs: Value, span: Span) -> Result<Value, EvalError> {
+    let expected = match op {
+        Op::And | Op::Or => Some("a boolean"),
+        Op::BitAnd | Op::BitOr | Op::BitXor | Op::ShiftLeft | Op::ShiftRight => Some("an integer"),
+        Op::Modulo | Op::Less | Op::LessEqual | Op::Greater | Op::GreaterEqual => {
+            Some("a real number")
+        }
+        _ => None,
+    };
+    if let Some(expected) = expected {
+        return Err(EvalError::TypeMismatch {
+            expected,
+            found: "a complex number",
+            span,
+        });
+    }
+    let base = lhs.expect_number(span)?.to_complex();
+    let operand = rhs.expect_number(span)?.to_complex();
+    let result = match op {
+        Op::Add => base + operand,
+        Op::Subtract => base - operand,
+        Op::Multiply => base * operand,
+        Op::Divide if operand.is_zero() => return Err(EvalError::DivisionByZero { span }),
+        Op::Divide => base / operand,
+        Op::Power => power(base, &rhs),
+        _ => return Ok(Value::Bool((base == operand) == matches!(op, Op::Equal))),
+    };
+    complex(result, span)
+}
+
+/// The principal value of `base ^ exponent`. Integer powers multiply out,
+/// which keeps `(1i)^2` free of rounding noise.
+pub(crate) fn power(base: Complex64, exponent: &Value) -> Complex64 {
+    let integer = match exponent {
+        Value::Real(val) if val.fract() == 0.0 => val.to_i32(),
+        val if val.is_integer() => val.to_bigint().to_i32(),
+        _ => None,
+    };
+    match integer {
+        Some(n) => base.powi(n),
+        None if exponent.to_f64() == 0.5 => base.sqrt(),
+        None => base.powc(exponent.to_complex()),
+    }
+}
+
+/// Writes `val` as `a + bi`, leaving out a zero real part.
+pub(crate) fn format(val: &Complex64, format: NumberFormat) -> String {
+    let im = Value::Real(val.im.abs()).format(format);
+    if val.re == 0.0 {
+        let sign = if val.im < 0.0 { "-" } else { "" };
+        return format!("{}{}i", sign, im);
+    }
+    let sign = if val.im < 0.0 { '-' } else { '+' };
+    format!("{} {} {}i", Value::Real(val.re).format(format), sign, im)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complex_display() {
+        let test_table = vec![
+            (Complex64::new(3.0, 2.0), NumberFormat::Fraction, "3 + 2i"),
+            (
+                Complex64::new(3.0, -2.5),
+                NumberFormat::Fraction,
+                "3 - 2.5i",
+            ),
+            (Complex64::new(0.0, 1.0), NumberFormat::Fraction, "1i"),
+            (Complex64::new(-0.0, -4.0), NumberFormat::Fraction, "-4i"),
+            (
+                Complex64::new(1500.0, -0.002),
+                NumberFormat::Engineering,
+                "1.5e3 - 2e-3i",
+            ),
+        ];
+        for (val, format, expected) in test_table.into_iter() {
+            assert_eq!(super::format(&val, format), expected, "{:?}", val);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{Quantity, Unit, Value};
+
+/// A named value that expressions can use without defining it first.
+#[derive(Debug, PartialEq)]
+pub struct Constant {
+    pub name: &'static str,
+    pub description: &'static str,
+    value: f64,
+    /// Units from `UNITS` and their exponents. Empty for plain numbers.
+    unit: &'static [(&'static str, i32)],
+}
+
+macro_rules! constant {
+    ($name:literal, $value:expr, $description:literal) => {
+        constant!($name, $value, [], $description)
+    };
+    ($name:literal, $value:expr, [$(($unit:literal, $exponent:expr)),*], $description:literal) => {
+        Constant {
+            name: $name,
+            description: $description,
+            value: $value,
+            unit: &[$(($unit, $exponent)),*],
+        }
+    };
+}
+
+/// Constants are looked up after variables, so `e = 5` hides Euler's
+/// number rather than failing. Physical constants carry their units and
+/// use the 2018 CODATA values. A number directly before `h` is a quantity
+/// in hours, so Planck's constant needs an explicit `*`, as in `2 * h`.
+pub const CONSTANTS: &[Constant] = &[
+    constant!(
+        "pi",
+        std::f64::consts::PI,
+        "ratio of a circle's circumference to its diameter"
+    ),
+    constant!("tau", std::f64::consts::TAU, "2 pi"),
+    constant!("e", std::f64::consts::E, "Euler's number"),
+    constant!("phi", 1.618033988749895, "golden ratio"),
+    constant!(
+        "c",
+        299792458.0,
+        [("m", 1), ("s", -1)],
+        "speed of light in a vacuum"
+    ),
+    constant!(
+        "G",
+        6.6743e-11,
+        [("m", 3), ("kg", -1), ("s", -2)],
+        "Newtonian constant of gravitation"
+    ),
+    constant!(
+        "g0",
+        9.80665,
+        [("m", 1), ("s", -2)],
+        "standard acceleration of gravity"
+    ),
+    constant!("h", 6.62607015e-34, [("J", 1), ("s", 1)], "Planck constant"),
+    constant!(
+        "hbar",
+        1.0545718176461565e-34,
+        [("J", 1), ("s", 1)],
+        "reduced Planck constant"
+    ),
+    constant!(
+        "k_B",
+        1.380649e-23,
+        [("J", 1), ("K", -1)],
+        "Boltzmann constant"
+    ),
+    constant!("N_A", 6.02214076e23, [("mol", -1)], "Avogadro constant"),
+    constant!("q_e", 1.602176634e-19, [("C", 1)], "elementary charge"),
+];
+
+impl Constant {
+    pub fn value(&self) -> Value {
+        if self.unit.is_empty() {
+            return Value::Real(self.value);
+        }
+        let unit = self
+            .unit
+            .iter()
+            .map(|(name, exponent)| Unit::named(name).unwrap().pow(*exponent).unwrap())
+            .fold(Unit::default(), |unit, term| unit.mul(&term).unwrap());
+        Value::Quantity(Quantity {
+            value: self.value,
+            unit,
+        })
+    }
+}
+
+pub fn lookup(name: &str) -> Option<&'static Constant> {
+    CONSTANTS.iter().find(|constant| constant.name == name)
+}
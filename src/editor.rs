@@ -0,0 +1,879 @@
+use crate::{BUILTINS, CONSTANTS};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+
+/// How many lines of history are kept between sessions.
+const MAX_HISTORY: usize = 1000;
+
+/// A key press, decoded from the bytes the terminal sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// Ctrl-C
+    Interrupt,
+    /// Ctrl-D, which ends input on an empty line
+    EndOfFile,
+    /// Ctrl-R
+    Search,
+    /// Ctrl-U
+    KillStart,
+    /// Ctrl-K
+    KillEnd,
+    /// Ctrl-W
+    KillWord,
+    /// Anything without a binding
+    Other,
+}
+
+/// Reads one key press, or `None` at the end of input.
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let first = match read_byte(input)? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+    let key = match first {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::EndOfFile,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x0b => Key::KillEnd,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x12 => Key::Search,
+        0x15 => Key::KillStart,
+        0x17 => Key::KillWord,
+        0x1b => read_escape(input)?,
+        byte if byte < 0x20 => Key::Other,
+        byte => read_char(input, byte)?,
+    };
+    Ok(Some(key))
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Decodes the rest of an escape sequence, like `ESC [ A` for up. Lone
+/// escapes and sequences we don't know are ignored.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    if !matches!(read_byte(input)?, Some(b'[' | b'O')) {
+        return Ok(Key::Other);
+    }
+    let key = match read_byte(input)? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        // Sequences like `ESC [ 3 ~` for delete
+        Some(digit @ b'0'..=b'9') => {
+            let mut code = vec![digit];
+            while let Some(byte) = read_byte(input)? {
+                if !byte.is_ascii_digit() {
+                    if byte != b'~' {
+                        return Ok(Key::Other);
+                    }
+                    break;
+                }
+                code.push(byte);
+            }
+            match &code[..] {
+                b"1" | b"7" => Key::Home,
+                b"4" | b"8" => Key::End,
+                b"3" => Key::Delete,
+                _ => Key::Other,
+            }
+        }
+        _ => Key::Other,
+    };
+    Ok(key)
+}
+
+/// Reads the rest of the UTF-8 character that starts with `first`.
+fn read_char(input: &mut impl Read, first: u8) -> io::Result<Key> {
+    let len = match first.leading_ones() {
+        0 => 1,
+        n @ 2..=4 => n as usize,
+        _ => return Ok(Key::Other),
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => return Ok(Key::Other),
+        }
+    }
+    Ok(match std::str::from_utf8(&bytes) {
+        Ok(s) => Key::Char(s.chars().next().unwrap()),
+        Err(_) => Key::Other,
+    })
+}
+
+/// What the editor should do after a key press.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Edit,
+    Accept,
+    Interrupt,
+    EndOfFile,
+    /// Show the names a completion could be
+    List(Vec<&'static str>),
+}
+
+/// A reverse search through the history.
+#[derive(Debug, Default)]
+struct Search {
+    query: String,
+    /// The history entry that matches the query
+    found: Option<usize>,
+}
+
+/// The line being edited.
+#[derive(Debug, Default)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+    /// The history entry shown, while moving through it with up and down
+    entry: Option<usize>,
+    /// What was typed before moving into the history
+    draft: Vec<char>,
+    search: Option<Search>,
+}
+
+impl Line {
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+}
+
+/// Reads lines from a terminal with editing, history and completion of
+/// built-in names. Input that isn't a terminal is read line by line.
+pub(crate) struct Editor {
+    history: Vec<String>,
+    /// Where history is saved, if anywhere
+    path: Option<PathBuf>,
+    /// Names that tab completes to, sorted
+    words: Vec<&'static str>,
+}
+
+impl Editor {
+    /// An editor with the history saved by earlier sessions.
+    pub fn new() -> Editor {
+        let path = history_path();
+        let mut history: Vec<String> = match &path {
+            Some(path) => fs::read_to_string(path)
+                .unwrap_or_default()
+                .lines()
+                .map(str::to_string)
+                .collect(),
+            None => Vec::new(),
+        };
+        if history.len() > MAX_HISTORY {
+            history.drain(..history.len() - MAX_HISTORY);
+            if let Some(path) = &path {
+                let _ = fs::write(path, history.join("\n") + "\n");
+            }
+        }
+        Editor::with_history(history, path)
+    }
+
+    fn with_history(history: Vec<String>, path: Option<PathBuf>) -> Editor {
+        let mut words: Vec<_> = BUILTINS
+            .iter()
+            .map(|builtin| builtin.name)
+            .chain(CONSTANTS.iter().map(|constant| constant.name))
+            .collect();
+        words.sort_unstable();
+        words.dedup();
+        Editor {
+            history,
+            path,
+            words,
+        }
+    }
+
+    /// Shows `prompt` and reads a line into `buf`, like
+    /// `io::Stdin::read_line`. Returns the number of bytes read, which is
+    /// 0 at the end of input.
+    pub fn read_line(&mut self, prompt: &str, buf: &mut String) -> io::Result<usize> {
+        #[cfg(unix)]
+        if let Some(_raw) = terminal::RawMode::enable() {
+            return self.read_edited(prompt, buf);
+        }
+        print!("{}", prompt);
+        io::stdout().flush()?;
+        io::stdin().read_line(buf)
+    }
+
+    #[cfg(unix)]
+    fn read_edited(&mut self, prompt: &str, buf: &mut String) -> io::Result<usize> {
+        let (mut input, mut out) = (io::stdin().lock(), io::stdout().lock());
+        let mut line = Line::default();
+        let mut drawn = Drawn::default();
+        // Asked before each redraw, since the terminal may have been resized
+        let width = || terminal::width().unwrap_or(80);
+        render(&mut out, prompt, &line, width(), &mut drawn)?;
+        loop {
+            let key = match read_key(&mut input)? {
+                Some(key) => key,
+                None => return Ok(0),
+            };
+            match self.handle(&mut line, key) {
+                Action::Edit => {}
+                Action::Accept => {
+                    render(&mut out, prompt, &line, width(), &mut drawn)?;
+                    leave(&mut out, &mut drawn)?;
+                    let text = line.text();
+                    self.add_history(&text);
+                    buf.push_str(&text);
+                    buf.push('\n');
+                    return Ok(text.len() + 1);
+                }
+                Action::Interrupt => {
+                    // Shown at the end of the line, like a shell does
+                    line.cursor = line.chars.len();
+                    render(&mut out, prompt, &line, width(), &mut drawn)?;
+                    writeln!(out, "^C")?;
+                    drawn = Drawn::default();
+                    line = Line::default();
+                }
+                Action::EndOfFile => {
+                    leave(&mut out, &mut drawn)?;
+                    return Ok(0);
+                }
+                Action::List(words) => {
+                    leave(&mut out, &mut drawn)?;
+                    writeln!(out, "{}", words.join("  "))?;
+                }
+            }
+            render(&mut out, prompt, &line, width(), &mut drawn)?;
+        }
+    }
+
+    /// Applies `key` to `line`.
+    fn handle(&self, line: &mut Line, key: Key) -> Action {
+        if line.search.is_some() {
+            match self.handle_search(line, key) {
+                Some(action) => return action,
+                // Other keys end the search and edit the line it found
+                None => line.search = None,
+            }
+        }
+        match key {
+            Key::Char(c) => {
+                line.chars.insert(line.cursor, c);
+                line.cursor += 1;
+            }
+            Key::Enter => return Action::Accept,
+            Key::Tab => return self.complete(line),
+            Key::Backspace if line.cursor > 0 => {
+                line.cursor -= 1;
+                line.chars.remove(line.cursor);
+            }
+            Key::EndOfFile if line.chars.is_empty() => return Action::EndOfFile,
+            Key::Delete | Key::EndOfFile if line.cursor < line.chars.len() => {
+                line.chars.remove(line.cursor);
+            }
+            Key::Left => line.cursor = line.cursor.saturating_sub(1),
+            Key::Right => line.cursor = (line.cursor + 1).min(line.chars.len()),
+            Key::Home => line.cursor = 0,
+            Key::End => line.cursor = line.chars.len(),
+            Key::Up => {
+                let entry = line.entry.unwrap_or(self.history.len());
+                if entry > 0 {
+                    if line.entry.is_none() {
+                        line.draft = line.chars.clone();
+                    }
+                    line.entry = Some(entry - 1);
+                    line.set(&self.history[entry - 1]);
+                }
+            }
+            Key::Down => match line.entry {
+                Some(entry) if entry + 1 < self.history.len() => {
+                    line.entry = Some(entry + 1);
+                    line.set(&self.history[entry + 1]);
+                }
+                Some(_) => {
+                    line.entry = None;
+                    line.chars = std::mem::take(&mut line.draft);
+                    line.cursor = line.chars.len();
+                }
+                None => {}
+            },
+            Key::KillStart => {
+                line.chars.drain(..line.cursor);
+                line.cursor = 0;
+            }
+            Key::KillEnd => line.chars.truncate(line.cursor),
+            Key::KillWord => {
+                let before = &line.chars[..line.cursor];
+                let end = before.iter().rposition(|c| !c.is_whitespace());
+                let start = end
+                    .and_then(|end| before[..end].iter().rposition(|c| c.is_whitespace()))
+                    .map_or(0, |space| space + 1);
+                line.chars.drain(start..line.cursor);
+                line.cursor = start;
+            }
+            Key::Search => line.search = Some(Search::default()),
+            Key::Interrupt => return Action::Interrupt,
+            Key::Backspace | Key::Delete | Key::EndOfFile | Key::Other => {}
+        }
+        Action::Edit
+    }
+
+    /// Applies `key` during a reverse search, or returns `None` if the key
+    /// ends the search.
+    fn handle_search(&self, line: &mut Line, key: Key) -> Option<Action> {
+        let search = line.search.as_mut().unwrap();
+        // Where to look back from: a longer query may still match the
+        // current entry, but searching again moves past it
+        let before = match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                search.found.map_or(self.history.len(), |found| found + 1)
+            }
+            Key::Backspace => {
+                search.query.pop();
+                self.history.len()
+            }
+            Key::Search => search.found.unwrap_or(self.history.len()),
+            Key::Enter => {
+                line.search = None;
+                return Some(Action::Accept);
+            }
+            Key::Interrupt => {
+                line.search = None;
+                return Some(Action::Interrupt);
+            }
+            _ => return None,
+        };
+        let found = self.history[..before]
+            .iter()
+            .rposition(|entry| entry.contains(&search.query));
+        if let Some(found) = found {
+            search.found = Some(found);
+            line.entry = Some(found);
+            line.set(&self.history[found]);
+        }
+        Some(Action::Edit)
+    }
+
+    /// Completes the name before the cursor as far as all the names it
+    /// could be agree, listing them if that adds nothing.
+    fn complete(&self, line: &mut Line) -> Action {
+        let before = &line.chars[..line.cursor];
+        let start = before
+            .iter()
+            .rposition(|c| !(c.is_ascii_alphanumeric() || *c == '_'))
+            .map_or(0, |i| i + 1);
+        let word: String = before[start..].iter().collect();
+        if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) {
+            return Action::Edit;
+        }
+        let matches: Vec<_> = self
+            .words
+            .iter()
+            .copied()
+            .filter(|name| name.starts_with(&word))
+            .collect();
+        let common = match matches.first() {
+            Some(first) => matches.iter().fold(*first, |common, name| {
+                let len = common
+                    .bytes()
+                    .zip(name.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &common[..len]
+            }),
+            None => return Action::Edit,
+        };
+        if common.len() > word.len() {
+            for c in common[word.len()..].chars() {
+                line.chars.insert(line.cursor, c);
+                line.cursor += 1;
+            }
+            return Action::Edit;
+        }
+        if matches.len() > 1 {
+            return Action::List(matches);
+        }
+        Action::Edit
+    }
+
+    fn add_history(&mut self, text: &str) {
+        if text.trim().is_empty() || self.history.last().is_some_and(|last| last == text) {
+            return;
+        }
+        self.history.push(text.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        // History is a convenience, so failing to save it isn't an error
+        if let Some(path) = &self.path {
+            let _ = path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| OpenOptions::new().create(true).append(true).open(path))
+                .and_then(|mut file| writeln!(file, "{}", text));
+        }
+    }
+}
+
+/// `$XDG_DATA_HOME/calc/history`, or `~/.local/share/calc/history` when
+/// that isn't set.
+fn history_path() -> Option<PathBuf> {
+    let data = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    Some(data.join("calc").join("history"))
+}
+
+/// How many columns `c` takes up in a terminal: two for wide East Asian
+/// characters and emoji, and none for combining marks.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x0300..=0x036f
+        | 0x1ab0..=0x1aff
+        | 0x1dc0..=0x1dff
+        | 0x200b..=0x200f
+        | 0x20d0..=0x20ff
+        | 0xfe00..=0xfe0f
+        | 0xfe20..=0xfe2f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+/// The row and column the terminal's cursor is left at after writing
+/// `chars` from the start of a row `width` columns wide. A character that
+/// doesn't fit in what's left of a row goes on the next one, and a row
+/// that's exactly filled leaves the cursor past its end.
+fn position(chars: impl IntoIterator<Item = char>, width: usize) -> (usize, usize) {
+    chars.into_iter().fold((0, 0), |(row, col), c| {
+        let char_width = char_width(c);
+        if col + char_width > width {
+            (row + 1, char_width)
+        } else {
+            (row, col + char_width)
+        }
+    })
+}
+
+/// Where the last render left the terminal, so the next can draw over it.
+#[derive(Debug, Default, PartialEq)]
+struct Drawn {
+    /// The cursor's row, counting from the prompt's
+    cursor_row: usize,
+    /// The row and column just past the end of the line
+    end: (usize, usize),
+}
+
+/// Redraws the line over the last render and puts the terminal's cursor
+/// where the line's is, wrapping at `width` columns.
+fn render(
+    out: &mut impl Write,
+    prompt: &str,
+    line: &Line,
+    width: usize,
+    drawn: &mut Drawn,
+) -> io::Result<()> {
+    let prefix = match &line.search {
+        Some(search) => format!("(reverse-i-search)`{}': ", search.query),
+        None => prompt.to_string(),
+    };
+    let width = width.max(2);
+    if drawn.cursor_row > 0 {
+        write!(out, "\x1b[{}A", drawn.cursor_row)?;
+    }
+    write!(out, "\r\x1b[J{}{}", prefix, line.text())?;
+    let mut end = position(prefix.chars().chain(line.chars.iter().copied()), width);
+    // Terminals wait for another character before wrapping a full row, so
+    // start the next row to know where the cursor is
+    if end.1 == width {
+        write!(out, "\r\n")?;
+        end = (end.0 + 1, 0);
+    }
+    let before = line.chars[..line.cursor].iter().copied();
+    let (mut row, mut col) = position(prefix.chars().chain(before), width);
+    let next = line.chars.get(line.cursor).map_or(0, |c| char_width(*c));
+    if col == width || col + next > width {
+        (row, col) = (row + 1, 0);
+    }
+    if end.0 > row {
+        write!(out, "\x1b[{}A", end.0 - row)?;
+    }
+    write!(out, "\r")?;
+    if col > 0 {
+        write!(out, "\x1b[{}C", col)?;
+    }
+    *drawn = Drawn {
+        cursor_row: row,
+        end,
+    };
+    out.flush()
+}
+
+/// Moves the terminal's cursor to the start of the row after the line,
+/// ready for other output.
+fn leave(out: &mut impl Write, drawn: &mut Drawn) -> io::Result<()> {
+    let (end_row, end_col) = drawn.end;
+    if end_row > drawn.cursor_row {
+        write!(out, "\x1b[{}B", end_row - drawn.cursor_row)?;
+    }
+    // A line that exactly fills its rows already ends at the start of one
+    if end_row > 0 && end_col == 0 {
+        write!(out, "\r")?;
+    } else {
+        write!(out, "\r\n")?;
+    }
+    *drawn = Drawn::default();
+    out.flush()
+}
+
+#[cfg(unix)]
+mod terminal {
+    use std::mem::MaybeUninit;
+
+    /// Keeps the terminal in raw mode, so key presses arrive one at a time
+    /// without echo, until dropped.
+    pub struct RawMode {
+        original: libc::termios,
+    }
+
+    impl RawMode {
+        /// Switches stdin to raw mode, or returns `None` if it isn't a
+        /// terminal.
+        pub fn enable() -> Option<RawMode> {
+            // SAFETY: tcgetattr fills in the termios it's given when it
+            // succeeds, and both calls only touch the stdin descriptor
+            unsafe {
+                if libc::isatty(libc::STDIN_FILENO) == 0 {
+                    return None;
+                }
+                let mut original = MaybeUninit::uninit();
+                if libc::tcgetattr(libc::STDIN_FILENO, original.as_mut_ptr()) != 0 {
+                    return None;
+                }
+                let original = original.assume_init();
+                let mut raw = original;
+                raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+                raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+                raw.c_cc[libc::VMIN] = 1;
+                raw.c_cc[libc::VTIME] = 0;
+                if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) != 0 {
+                    return None;
+                }
+                Some(RawMode { original })
+            }
+        }
+    }
+
+    /// The width of the terminal stdout writes to, in columns.
+    pub fn width() -> Option<usize> {
+        // SAFETY: TIOCGWINSZ fills in the winsize it's given when it
+        // succeeds, and only reads the stdout descriptor
+        unsafe {
+            let mut size = MaybeUninit::<libc::winsize>::uninit();
+            if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, size.as_mut_ptr()) != 0 {
+                return None;
+            }
+            let columns = size.assume_init().ws_col;
+            (columns > 0).then_some(usize::from(columns))
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            // SAFETY: restores the settings read in `enable`
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(history: &[&str]) -> Editor {
+        Editor::with_history(history.iter().map(|s| s.to_string()).collect(), None)
+    }
+
+    /// Types `keys` into a fresh line, returning the last action.
+    fn type_keys(editor: &Editor, line: &mut Line, keys: &[Key]) -> Action {
+        keys.iter()
+            .map(|key| editor.handle(line, *key))
+            .last()
+            .unwrap()
+    }
+
+    fn chars(s: &str) -> Vec<Key> {
+        s.chars().map(Key::Char).collect()
+    }
+
+    #[test]
+    fn decode_keys() {
+        let bytes = b"a\x1b[A\x1b[3~\x7f\x12\r\xc2\xb0\x1b[5~";
+        let mut input = &bytes[..];
+        let mut keys = Vec::new();
+        while let Some(key) = read_key(&mut input).unwrap() {
+            keys.push(key);
+        }
+        assert_eq!(
+            keys,
+            [
+                Key::Char('a'),
+                Key::Up,
+                Key::Delete,
+                Key::Backspace,
+                Key::Search,
+                Key::Enter,
+                Key::Char('°'),
+                Key::Other,
+            ]
+        );
+    }
+
+    #[test]
+    fn editing() {
+        let editor = editor(&[]);
+        let test_table = vec![
+            (chars("1+2"), "1+2", 3),
+            (
+                [chars("12"), vec![Key::Left, Key::Char('x')]].concat(),
+                "1x2",
+                2,
+            ),
+            (
+                [chars("123"), vec![Key::Home, Key::Delete]].concat(),
+                "23",
+                0,
+            ),
+            (
+                [chars("123"), vec![Key::Backspace, Key::End]].concat(),
+                "12",
+                2,
+            ),
+            ([chars("1 + 23"), vec![Key::KillWord]].concat(), "1 + ", 4),
+            ([chars("1 + 23 "), vec![Key::KillWord]].concat(), "1 + ", 4),
+            (
+                [chars("1+2"), vec![Key::Left, Key::KillStart]].concat(),
+                "2",
+                0,
+            ),
+            (
+                [chars("1+2"), vec![Key::Left, Key::KillEnd]].concat(),
+                "1+",
+                2,
+            ),
+        ];
+        for (keys, text, cursor) in test_table.into_iter() {
+            let mut line = Line::default();
+            type_keys(&editor, &mut line, &keys);
+            assert_eq!(
+                (line.text(), line.cursor),
+                (text.to_string(), cursor),
+                "{}",
+                text
+            );
+        }
+
+        let mut line = Line::default();
+        assert_eq!(editor.handle(&mut line, Key::EndOfFile), Action::EndOfFile);
+        assert_eq!(type_keys(&editor, &mut line, &chars("2")), Action::Edit);
+        assert_eq!(editor.handle(&mut line, Key::Enter), Action::Accept);
+    }
+
+    #[test]
+    fn history() {
+        let editor = editor(&["1 + 1", "x = 2", "sqrt(x)"]);
+        let mut line = Line::default();
+        type_keys(&editor, &mut line, &chars("draft"));
+        type_keys(&editor, &mut line, &[Key::Up, Key::Up]);
+        assert_eq!(line.text(), "x = 2");
+        type_keys(&editor, &mut line, &[Key::Up, Key::Up]);
+        assert_eq!(line.text(), "1 + 1");
+        type_keys(&editor, &mut line, &[Key::Down]);
+        assert_eq!(line.text(), "x = 2");
+        type_keys(&editor, &mut line, &[Key::Down, Key::Down]);
+        assert_eq!(line.text(), "draft");
+
+        let mut line = Line::default();
+        type_keys(&editor, &mut line, &[Key::Search, Key::Char('x')]);
+        assert_eq!(line.text(), "sqrt(x)");
+        // Searching again finds older matches
+        type_keys(&editor, &mut line, &[Key::Search]);
+        assert_eq!(line.text(), "x = 2");
+        type_keys(&editor, &mut line, &[Key::Search]);
+        assert_eq!(line.text(), "x = 2");
+        // Other keys end the search and edit what it found
+        type_keys(&editor, &mut line, &[Key::Home, Key::Char('y')]);
+        assert_eq!(line.text(), "yx = 2");
+        assert!(line.search.is_none());
+
+        let mut line = Line::default();
+        let action = type_keys(
+            &editor,
+            &mut line,
+            &[Key::Search, Key::Char('+'), Key::Enter],
+        );
+        assert_eq!((action, line.text()), (Action::Accept, "1 + 1".to_string()));
+
+        // Blank lines and repeats aren't recorded
+        let mut editor = editor;
+        editor.add_history("1 + 1");
+        editor.add_history("2");
+        editor.add_history("2");
+        editor.add_history(" ");
+        assert_eq!(editor.history, ["1 + 1", "x = 2", "sqrt(x)", "1 + 1", "2"]);
+    }
+
+    #[test]
+    fn completion() {
+        let editor = editor(&[]);
+        let test_table = vec![
+            ("sq", "sqrt", Action::Edit),
+            ("2 * tr", "2 * transpose", Action::Edit),
+            ("hb", "hbar", Action::Edit),
+            ("nope", "nope", Action::Edit),
+            ("12", "12", Action::Edit),
+            ("co", "co", Action::List(vec!["conj", "cos", "count"])),
+        ];
+        for (typed, text, action) in test_table.into_iter() {
+            let mut line = Line::default();
+            type_keys(&editor, &mut line, &chars(typed));
+            assert_eq!(editor.handle(&mut line, Key::Tab), action, "{}", typed);
+            assert_eq!(line.text(), text, "{}", typed);
+        }
+    }
+
+    #[test]
+    fn widths() {
+        let test_table = vec![
+            ('a', 1),
+            ('°', 1),
+            ('\u{301}', 0),
+            ('日', 2),
+            ('한', 2),
+            ('Ａ', 2),
+            ('😀', 2),
+        ];
+        for (c, width) in test_table.into_iter() {
+            assert_eq!(char_width(c), width, "{}", c);
+        }
+        assert_eq!(position("e\u{301}x".chars(), 80), (0, 2));
+        assert_eq!(position("123456".chars(), 6), (0, 6));
+        assert_eq!(position("1234567".chars(), 6), (1, 1));
+        // A wide character that doesn't fit starts the next row
+        assert_eq!(position("1234日".chars(), 5), (1, 2));
+    }
+
+    #[test]
+    fn rendering() {
+        // (text, cursor, width, rows above the cursor before, output, after)
+        let test_table = vec![
+            ("1+2", 3, 80, 0, "\r\x1b[J> 1+2\r\x1b[5C", (0, (0, 5))),
+            ("1+2", 0, 80, 0, "\r\x1b[J> 1+2\r\x1b[2C", (0, (0, 5))),
+            // Long lines wrap, and the cursor moves up to its row
+            (
+                "12345678",
+                1,
+                6,
+                0,
+                "\r\x1b[J> 12345678\x1b[1A\r\x1b[3C",
+                (0, (1, 4)),
+            ),
+            // Redrawing starts from the prompt's row
+            (
+                "12345678",
+                8,
+                6,
+                1,
+                "\x1b[1A\r\x1b[J> 12345678\r\x1b[4C",
+                (1, (1, 4)),
+            ),
+            // Filling a row exactly moves on to the next
+            ("1234", 4, 6, 0, "\r\x1b[J> 1234\r\n\r", (1, (1, 0))),
+            // Wide characters take two columns and don't split across rows
+            ("日本語", 2, 6, 0, "\r\x1b[J> 日本語\r", (1, (1, 2))),
+            ("日本", 1, 5, 0, "\r\x1b[J> 日本\r", (1, (1, 2))),
+            // Combining marks take none
+            (
+                "e\u{301}+1",
+                2,
+                80,
+                0,
+                "\r\x1b[J> e\u{301}+1\r\x1b[3C",
+                (0, (0, 5)),
+            ),
+        ];
+        for (text, cursor, width, rows, output, (cursor_row, end)) in test_table.into_iter() {
+            let mut line = Line::default();
+            line.set(text);
+            line.cursor = cursor;
+            let mut drawn = Drawn {
+                cursor_row: rows,
+                end: (rows, 0),
+            };
+            let mut out = Vec::new();
+            render(&mut out, "> ", &line, width, &mut drawn).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), output, "{}", text);
+            assert_eq!(drawn, Drawn { cursor_row, end }, "{}", text);
+        }
+
+        // Leaving starts a row below the line, unless it already ends at one
+        for (cursor_row, end, output) in [
+            (0, (0, 5), "\r\n"),
+            (0, (2, 4), "\x1b[2B\r\n"),
+            (1, (1, 0), "\r"),
+        ] {
+            let mut drawn = Drawn { cursor_row, end };
+            let mut out = Vec::new();
+            leave(&mut out, &mut drawn).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), output);
+            assert_eq!(drawn, Drawn::default());
+        }
+    }
+}
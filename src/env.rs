@@ -0,0 +1,187 @@
+use crate::error::{EvalError, Span};
+use crate::{Expr, OverflowMode, Value};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How deeply calls to user-defined functions may nest before evaluation
+/// gives up, so runaway recursion reports an error instead of overflowing
+/// the stack.
+pub const MAX_CALL_DEPTH: usize = 200;
+
+/// A function defined in the calculator language, e.g. `f(x, y) = x^2 + y`.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+/// Variable and function bindings visible to an evaluation, and the
+/// settings that change how it behaves.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    variables: HashMap<String, Value>,
+    functions: HashMap<String, Function>,
+    results: Vec<Value>,
+    overflow_mode: OverflowMode,
+    interrupt: Option<&'static AtomicBool>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    /// All bindings, sorted by name.
+    pub fn variables(&self) -> Vec<(&str, &Value)> {
+        let mut variables: Vec<_> = self
+            .variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        variables.sort_by_key(|(name, _)| *name);
+        variables
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    pub fn define(&mut self, name: &str, function: Function) {
+        self.functions.insert(name.to_string(), function);
+    }
+
+    /// Forgets all variables, functions and results, keeping the settings.
+    pub fn clear(&mut self) {
+        self.variables.clear();
+        self.functions.clear();
+        self.results.clear();
+    }
+
+    /// The `n`th recorded result, counting from 1, as referenced by `$n`.
+    pub fn result(&self, n: usize) -> Option<&Value> {
+        self.results.get(n.checked_sub(1)?)
+    }
+
+    /// The most recent result, which `ans` and `_` refer to.
+    pub fn last_result(&self) -> Option<&Value> {
+        self.results.last()
+    }
+
+    /// Records `value` as the next result and returns its number.
+    pub fn push_result(&mut self, value: Value) -> usize {
+        self.results.push(value);
+        self.results.len()
+    }
+
+    /// How fixed-width integer arithmetic treats results out of range.
+    pub fn overflow_mode(&self) -> OverflowMode {
+        self.overflow_mode
+    }
+
+    pub fn set_overflow_mode(&mut self, mode: OverflowMode) {
+        self.overflow_mode = mode;
+    }
+
+    /// Makes evaluation stop with `EvalError::Interrupted` while `flag` is
+    /// set, so a signal handler can cancel a long calculation.
+    pub fn set_interrupt_flag(&mut self, flag: &'static AtomicBool) {
+        self.interrupt = Some(flag);
+    }
+
+    pub fn interrupted(&self) -> bool {
+        self.interrupt
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Makes `check_interrupt` watch this environment's interrupt flag
+    /// until the guard is dropped.
+    pub(crate) fn watch_interrupts(&self) -> InterruptGuard {
+        InterruptGuard(WATCHED.replace(self.interrupt))
+    }
+
+    /// All user-defined functions, sorted by name.
+    pub fn functions(&self) -> Vec<(&str, &Function)> {
+        let mut functions: Vec<_> = self
+            .functions
+            .iter()
+            .map(|(name, function)| (name.as_str(), function))
+            .collect();
+        functions.sort_by_key(|(name, _)| *name);
+        functions
+    }
+}
+
+thread_local! {
+    /// The interrupt flag of the evaluation running on this thread, for
+    /// built-in functions and arithmetic, which don't see the environment.
+    static WATCHED: Cell<Option<&'static AtomicBool>> = const { Cell::new(None) };
+}
+
+/// Restores the flag watched before `Environment::watch_interrupts`.
+pub(crate) struct InterruptGuard(Option<&'static AtomicBool>);
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        WATCHED.set(self.0);
+    }
+}
+
+/// Fails with `EvalError::Interrupted` once the evaluation running on this
+/// thread has been interrupted. Long loops call this as they go.
+pub(crate) fn check_interrupt(span: Span) -> Result<(), EvalError> {
+    match WATCHED.get() {
+        Some(flag) if flag.load(Ordering::Relaxed) => Err(EvalError::Interrupted { span }),
+        _ => Ok(()),
+    }
+}
+
+/// The names visible at one point of an evaluation: the parameters of the
+/// function being called, if any, over the global environment. A function
+/// body sees its own parameters and the globals, never its caller's
+/// parameters.
+pub(crate) struct Scope<'a> {
+    pub env: &'a Environment,
+    locals: Vec<(&'a str, Value)>,
+    pub depth: usize,
+}
+
+impl<'a> Scope<'a> {
+    pub fn global(env: &'a Environment) -> Scope<'a> {
+        Scope {
+            env,
+            locals: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Value> {
+        self.locals
+            .iter()
+            .find(|(local, _)| *local == name)
+            .map(|(_, value)| value)
+            .or_else(|| self.env.get(name))
+    }
+
+    /// The scope for evaluating the body of `function` with `args`.
+    pub fn enter(&self, function: &'a Function, args: Vec<Value>) -> Scope<'a> {
+        Scope {
+            env: self.env,
+            locals: function
+                .params
+                .iter()
+                .map(String::as_str)
+                .zip(args)
+                .collect(),
+            depth: self.depth + 1,
+        }
+    }
+}
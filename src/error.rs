@@ -0,0 +1,449 @@
+use crate::functions::Arity;
+use crate::{NumberFormat, Rule};
+use pest::error::{ErrorVariant, InputLocation};
+use std::fmt;
+
+/// Byte range of a piece of source text, used to point errors at the
+/// sub-expression that caused them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Span {
+        Span::new(span.start(), span.end())
+    }
+}
+
+/// Errors raised while evaluating an `Expr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    DivisionByZero {
+        span: Span,
+    },
+    Overflow {
+        span: Span,
+    },
+    NegativeExponent {
+        span: Span,
+    },
+    /// A bit shift by a negative amount, e.g. `1 << -1`.
+    NegativeShift {
+        span: Span,
+    },
+    /// The result isn't a real number, e.g. `ln(0)`.
+    Domain {
+        span: Span,
+    },
+    UndefinedVariable {
+        name: String,
+        span: Span,
+    },
+    UnknownFunction {
+        name: String,
+        span: Span,
+    },
+    /// A reference like `$4` to a result that hasn't been recorded.
+    NoSuchResult {
+        index: usize,
+        span: Span,
+    },
+    /// A function was called with the wrong number of arguments.
+    ArityMismatch {
+        name: String,
+        expected: Arity,
+        found: usize,
+        span: Span,
+    },
+    /// An operand of the wrong kind, e.g. `1 + true`.
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+        span: Span,
+    },
+    /// User-defined functions nested deeper than `MAX_CALL_DEPTH`.
+    RecursionLimit {
+        name: String,
+        span: Span,
+    },
+    /// Quantities that would need to be in the same unit, e.g. `1 m + 1 s`.
+    DimensionMismatch {
+        lhs: String,
+        rhs: String,
+        span: Span,
+    },
+    /// Matrices whose shapes don't fit the operation, e.g. adding a 2x2
+    /// matrix to a 3x3 one.
+    ShapeMismatch {
+        lhs: String,
+        rhs: String,
+        span: Span,
+    },
+    /// An operation that needs a square matrix, like `det`, was given
+    /// another shape.
+    NotSquare {
+        shape: String,
+        span: Span,
+    },
+    /// Inverting or solving with a matrix whose determinant is zero.
+    SingularMatrix {
+        span: Span,
+    },
+    /// A function was called with arguments it can't work with.
+    InvalidArgument {
+        name: String,
+        reason: String,
+        span: Span,
+    },
+    /// Evaluation was stopped by the environment's interrupt flag.
+    Interrupted {
+        span: Span,
+    },
+}
+
+impl EvalError {
+    pub fn span(&self) -> Span {
+        match self {
+            EvalError::DivisionByZero { span }
+            | EvalError::Overflow { span }
+            | EvalError::NegativeExponent { span }
+            | EvalError::NegativeShift { span }
+            | EvalError::Domain { span }
+            | EvalError::UndefinedVariable { span, .. }
+            | EvalError::UnknownFunction { span, .. }
+            | EvalError::NoSuchResult { span, .. }
+            | EvalError::ArityMismatch { span, .. }
+            | EvalError::TypeMismatch { span, .. }
+            | EvalError::RecursionLimit { span, .. }
+            | EvalError::DimensionMismatch { span, .. }
+            | EvalError::ShapeMismatch { span, .. }
+            | EvalError::NotSquare { span, .. }
+            | EvalError::SingularMatrix { span }
+            | EvalError::InvalidArgument { span, .. }
+            | EvalError::Interrupted { span } => *span,
+        }
+    }
+
+    /// The same error, reported at `span` instead.
+    pub fn with_span(mut self, span: Span) -> EvalError {
+        match &mut self {
+            EvalError::DivisionByZero { span: old }
+            | EvalError::Overflow { span: old }
+            | EvalError::NegativeExponent { span: old }
+            | EvalError::NegativeShift { span: old }
+            | EvalError::Domain { span: old }
+            | EvalError::UndefinedVariable { span: old, .. }
+            | EvalError::UnknownFunction { span: old, .. }
+            | EvalError::NoSuchResult { span: old, .. }
+            | EvalError::ArityMismatch { span: old, .. }
+            | EvalError::TypeMismatch { span: old, .. }
+            | EvalError::RecursionLimit { span: old, .. }
+            | EvalError::DimensionMismatch { span: old, .. }
+            | EvalError::ShapeMismatch { span: old, .. }
+            | EvalError::NotSquare { span: old, .. }
+            | EvalError::SingularMatrix { span: old }
+            | EvalError::InvalidArgument { span: old, .. }
+            | EvalError::Interrupted { span: old } => *old = span,
+        }
+        self
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::DivisionByZero { .. } => write!(f, "division by zero"),
+            EvalError::Overflow { .. } => write!(f, "arithmetic overflow"),
+            EvalError::NegativeExponent { .. } => {
+                write!(f, "negative exponent in integer power")
+            }
+            EvalError::NegativeShift { .. } => write!(f, "negative shift amount"),
+            EvalError::Domain { .. } => write!(f, "result is not a real number"),
+            EvalError::UndefinedVariable { name, .. } => {
+                write!(f, "undefined variable '{}'", name)
+            }
+            EvalError::UnknownFunction { name, .. } => write!(f, "unknown function '{}'", name),
+            EvalError::NoSuchResult { index, .. } => write!(f, "no result ${} yet", index),
+            EvalError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(f, "{} takes {} but was given {}", name, expected, found),
+            EvalError::TypeMismatch {
+                expected, found, ..
+            } => write!(f, "expected {}, found {}", expected, found),
+            EvalError::RecursionLimit { name, .. } => {
+                write!(f, "maximum call depth exceeded in {}", name)
+            }
+            EvalError::DimensionMismatch { lhs, rhs, .. } => {
+                write!(f, "incompatible units: {} and {}", lhs, rhs)
+            }
+            EvalError::ShapeMismatch { lhs, rhs, .. } => {
+                write!(f, "incompatible shapes: {} and {}", lhs, rhs)
+            }
+            EvalError::NotSquare { shape, .. } => {
+                write!(f, "expected a square matrix, found {}", shape)
+            }
+            EvalError::SingularMatrix { .. } => write!(f, "matrix is singular"),
+            EvalError::InvalidArgument { name, reason, .. } => {
+                write!(f, "invalid argument to {}: {}", name, reason)
+            }
+            EvalError::Interrupted { .. } => write!(f, "interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// What went wrong while parsing a line of input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The input did not match the grammar. `expected` lists what would have
+    /// been accepted at that point, phrased for end users.
+    UnexpectedInput {
+        expected: Vec<String>,
+        found: String,
+    },
+    /// A numeric literal that doesn't fit the type used to represent it.
+    LiteralOutOfRange { literal: String },
+    /// A function definition names the same parameter twice.
+    DuplicateParameter { name: String },
+    /// A name after a number or `to` that isn't in the unit table.
+    UnknownUnit { name: String },
+    /// A display suffix like `in hex` naming a format that doesn't exist.
+    UnknownFormat { name: String },
+    /// A matrix literal whose rows aren't all the same length.
+    RaggedMatrix,
+    /// Juxtaposition like `2(3)` when the syntax is strict.
+    ImplicitMultiplication,
+    /// Brackets or conditionals nested more than `MAX_NESTING` deep.
+    NestingTooDeep { limit: usize },
+    /// An expression whose operations would nest more than `MAX_DEPTH`
+    /// deep, like a sum of thousands of terms.
+    ExpressionTooDeep { limit: usize },
+    /// Input the grammar accepts but the expression builder can't handle.
+    UnsupportedSyntax { text: String },
+}
+
+/// A syntax error with its position in the source text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+    /// 1-based line of `span.start`.
+    pub line: usize,
+    /// 1-based column (in characters) of `span.start`.
+    pub column: usize,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span, source: &str) -> ParseError {
+        let (line, column) = line_col(source, span.start);
+        ParseError {
+            kind,
+            span,
+            line,
+            column,
+        }
+    }
+
+    pub(crate) fn from_span(kind: ParseErrorKind, span: pest::Span<'_>) -> ParseError {
+        let (line, column) = span.start_pos().line_col();
+        ParseError {
+            kind,
+            span: span.into(),
+            line,
+            column,
+        }
+    }
+
+    pub(crate) fn from_pest(err: pest::error::Error<Rule>, source: &str) -> ParseError {
+        let span = match err.location {
+            InputLocation::Pos(pos) => Span::new(pos, pos),
+            InputLocation::Span((start, end)) => Span::new(start, end),
+        };
+        let mut expected = Vec::new();
+        if let ErrorVariant::ParsingError { positives, .. } = &err.variant {
+            for rule in positives {
+                for description in describe_rule(*rule) {
+                    if !expected.contains(&description) {
+                        expected.push(description);
+                    }
+                }
+            }
+            // Literal tokens aren't reported by pest, so infer a missing
+            // closing paren from the input itself.
+            let prefix = &source[..span.start];
+            let unclosed = prefix.matches('(').count() > prefix.matches(')').count();
+            if unclosed && positives.iter().any(|rule| is_operator(*rule)) {
+                expected.push("')'".to_string());
+            }
+        }
+        let found = match source[span.start..].chars().next() {
+            Some(c) => format!("'{}'", c),
+            None => "end of input".to_string(),
+        };
+        ParseError::new(
+            ParseErrorKind::UnexpectedInput { expected, found },
+            span,
+            source,
+        )
+    }
+
+    /// Renders the error with the offending source line and a caret marker
+    /// underneath the error position.
+    pub fn render(&self, source: &str) -> String {
+        render_snippet(source, self.span, &self.to_string())
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::UnexpectedInput { expected, found } if expected.is_empty() => {
+                write!(f, "unexpected {}", found)
+            }
+            ParseErrorKind::UnexpectedInput { expected, found } => {
+                write!(
+                    f,
+                    "expected {}, found {}",
+                    join_alternatives(expected),
+                    found
+                )
+            }
+            ParseErrorKind::LiteralOutOfRange { literal } => {
+                write!(f, "number {} is out of range", literal)
+            }
+            ParseErrorKind::DuplicateParameter { name } => {
+                write!(f, "parameter '{}' is declared more than once", name)
+            }
+            ParseErrorKind::UnknownUnit { name } => write!(f, "unknown unit '{}'", name),
+            ParseErrorKind::UnknownFormat { name } => write!(
+                f,
+                "unknown format '{}', expected one of: {}",
+                name,
+                NumberFormat::NAMES.join(", ")
+            ),
+            ParseErrorKind::ImplicitMultiplication => {
+                write!(f, "implicit multiplication isn't allowed in strict syntax")
+            }
+            ParseErrorKind::RaggedMatrix => write!(f, "matrix rows have different lengths"),
+            ParseErrorKind::NestingTooDeep { limit } => {
+                write!(f, "brackets and conditionals nest more than {} deep", limit)
+            }
+            ParseErrorKind::ExpressionTooDeep { limit } => {
+                write!(f, "expression is more than {} operations deep", limit)
+            }
+            ParseErrorKind::UnsupportedSyntax { text } => {
+                write!(f, "unsupported syntax '{}'", text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn describe_rule(rule: Rule) -> Vec<String> {
+    let descriptions: &[&str] = match rule {
+        Rule::integer
+        | Rule::hex
+        | Rule::binary
+        | Rule::octal
+        | Rule::imaginary
+        | Rule::decimal
+        | Rule::quantity
+        | Rule::boolean
+        | Rule::matrix
+        | Rule::conditional
+        | Rule::ident
+        | Rule::history_ref
+        | Rule::call
+        | Rule::expr => &["a number", "a name", "'('"],
+        rule if is_operator(rule) => &["an operator"],
+        Rule::EOI => &["end of input"],
+        _ => &[],
+    };
+    descriptions.iter().map(|d| d.to_string()).collect()
+}
+
+fn is_operator(rule: Rule) -> bool {
+    matches!(
+        rule,
+        Rule::add
+            | Rule::subtract
+            | Rule::multiply
+            | Rule::divide
+            | Rule::modulo
+            | Rule::power
+            | Rule::bit_and
+            | Rule::bit_or
+            | Rule::xor
+            | Rule::shift_left
+            | Rule::shift_right
+            | Rule::range
+            | Rule::factorial
+            | Rule::percent
+            | Rule::degree
+            | Rule::eq
+            | Rule::ne
+            | Rule::lt
+            | Rule::le
+            | Rule::gt
+            | Rule::ge
+            | Rule::and
+            | Rule::or
+    )
+}
+
+/// Joins alternatives as "a, b or c".
+fn join_alternatives(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.clone(),
+        [init @ .., last] => format!("{} or {}", init.join(", "), last),
+    }
+}
+
+/// 1-based line and column of a byte offset into `source`.
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+/// Formats `message` above the source line containing `span`, with carets
+/// marking the spanned text.
+pub(crate) fn render_snippet(source: &str, span: Span, message: &str) -> String {
+    let (line, column) = line_col(source, span.start);
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    let line_start = source[..span.start.min(source.len())]
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    let line_end = line_start + text.len();
+    let width = source
+        .get(span.start..span.end.min(line_end))
+        .map_or(0, |s| s.chars().count())
+        .max(1);
+    let gutter = " ".repeat(line.to_string().len());
+    format!(
+        "error: {message}\n{gutter} --> {line}:{column}\n{gutter} |\n{line} | {text}\n{gutter} | {pad}{carets}",
+        pad = " ".repeat(column - 1),
+        carets = "^".repeat(width),
+    )
+}
@@ -0,0 +1,191 @@
+use crate::error::{EvalError, Span};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::ToPrimitive;
+use std::fmt;
+use std::str::FromStr;
+
+/// A fixed-width integer type, for register and flag math. Values are
+/// created with the conversion function of the same name, e.g. `u8(200)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl IntType {
+    pub const ALL: [IntType; 8] = [
+        IntType::I8,
+        IntType::I16,
+        IntType::I32,
+        IntType::I64,
+        IntType::U8,
+        IntType::U16,
+        IntType::U32,
+        IntType::U64,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IntType::I8 => "i8",
+            IntType::I16 => "i16",
+            IntType::I32 => "i32",
+            IntType::I64 => "i64",
+            IntType::U8 => "u8",
+            IntType::U16 => "u16",
+            IntType::U32 => "u32",
+            IntType::U64 => "u64",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<IntType> {
+        IntType::ALL.into_iter().find(|ty| ty.name() == name)
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            IntType::I8 | IntType::U8 => 8,
+            IntType::I16 | IntType::U16 => 16,
+            IntType::I32 | IntType::U32 => 32,
+            IntType::I64 | IntType::U64 => 64,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(
+            self,
+            IntType::I8 | IntType::I16 | IntType::I32 | IntType::I64
+        )
+    }
+
+    pub fn min(self) -> i128 {
+        if self.is_signed() {
+            -(1 << (self.bits() - 1))
+        } else {
+            0
+        }
+    }
+
+    pub fn max(self) -> i128 {
+        if self.is_signed() {
+            (1 << (self.bits() - 1)) - 1
+        } else {
+            (1 << self.bits()) - 1
+        }
+    }
+
+    /// Keeps the low `bits()` bits of `value`, read as two's complement for
+    /// signed types.
+    pub fn wrap(self, value: &BigInt) -> i128 {
+        let modulus = BigInt::from(1) << self.bits();
+        // Always below 2^64, so it fits
+        let low = value.mod_floor(&modulus).to_i128().unwrap_or(0);
+        if low > self.max() {
+            low - (1 << self.bits())
+        } else {
+            low
+        }
+    }
+
+    /// Brings the exact result of an operation into range, as `mode` says.
+    pub(crate) fn fit(
+        self,
+        value: BigInt,
+        mode: OverflowMode,
+        span: Span,
+    ) -> Result<i128, EvalError> {
+        match value.to_i128() {
+            Some(val) if (self.min()..=self.max()).contains(&val) => Ok(val),
+            _ if mode == OverflowMode::Wrapping => Ok(self.wrap(&value)),
+            _ => Err(EvalError::Overflow { span }),
+        }
+    }
+}
+
+impl fmt::Display for IntType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What fixed-width arithmetic does with results outside its type's range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    /// Report an overflow error.
+    #[default]
+    Checked,
+    /// Keep the low bits, as the hardware would.
+    Wrapping,
+}
+
+impl OverflowMode {
+    pub const NAMES: &'static [&'static str] = &["checked", "wrapping"];
+}
+
+impl FromStr for OverflowMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OverflowMode, String> {
+        match s {
+            "checked" => Ok(OverflowMode::Checked),
+            "wrapping" => Ok(OverflowMode::Wrapping),
+            _ => Err(format!(
+                "unknown overflow mode '{}', expected one of: {}",
+                s,
+                OverflowMode::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OverflowMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverflowMode::Checked => write!(f, "checked"),
+            OverflowMode::Wrapping => write!(f, "wrapping"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_and_wrapping() {
+        assert_eq!((IntType::I8.min(), IntType::I8.max()), (-128, 127));
+        assert_eq!(IntType::U64.max(), u64::MAX as i128);
+        assert_eq!(IntType::I64.min(), i64::MIN as i128);
+
+        let test_table = vec![
+            (IntType::U8, 256, 0),
+            (IntType::U8, -1, 255),
+            (IntType::I8, 128, -128),
+            (IntType::I8, -129, 127),
+            (IntType::I16, 70000, 4464),
+            (IntType::U32, -2, u32::MAX as i128 - 1),
+        ];
+        for (ty, value, expected) in test_table.into_iter() {
+            assert_eq!(ty.wrap(&BigInt::from(value)), expected, "{} {}", ty, value);
+        }
+
+        let span = Span::new(0, 1);
+        let big = BigInt::from(300);
+        assert_eq!(
+            IntType::U8.fit(big.clone(), OverflowMode::Wrapping, span),
+            Ok(44)
+        );
+        assert_eq!(
+            IntType::U8.fit(big, OverflowMode::Checked, span),
+            Err(EvalError::Overflow { span })
+        );
+        assert_eq!(IntType::from_name("u16"), Some(IntType::U16));
+        assert_eq!(IntType::from_name("u128"), None);
+    }
+}
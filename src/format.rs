@@ -0,0 +1,229 @@
+use crate::complex;
+use crate::units::rounded;
+use crate::Value;
+use num_bigint::BigInt;
+use num_traits::Signed;
+use std::fmt;
+use std::str::FromStr;
+
+/// How results are written out for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberFormat {
+    /// Exact values as they are held, so rationals show as `a/b`.
+    #[default]
+    Fraction,
+    /// Rationals show as their decimal approximation.
+    Decimal,
+    /// Integers in base 16, e.g. `0xff`. Fixed-width integers show their
+    /// two's complement bits, so `i8(-1)` is `0xff`.
+    Hex,
+    Binary,
+    Octal,
+    /// One digit before the point, e.g. `1.2345e4`.
+    Scientific,
+    /// Exponents that are multiples of three, e.g. `12.345e3`.
+    Engineering,
+    /// Integers in every base, and fractions as both fraction and decimal.
+    All,
+}
+
+impl NumberFormat {
+    pub const NAMES: &'static [&'static str] = &[
+        "fraction",
+        "decimal",
+        "hex",
+        "binary",
+        "octal",
+        "scientific",
+        "engineering",
+        "all",
+    ];
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NumberFormat, String> {
+        match s {
+            "fraction" => Ok(NumberFormat::Fraction),
+            "decimal" | "dec" => Ok(NumberFormat::Decimal),
+            "hex" => Ok(NumberFormat::Hex),
+            "binary" | "bin" => Ok(NumberFormat::Binary),
+            "octal" | "oct" => Ok(NumberFormat::Octal),
+            "scientific" | "sci" => Ok(NumberFormat::Scientific),
+            "engineering" | "eng" => Ok(NumberFormat::Engineering),
+            "all" => Ok(NumberFormat::All),
+            _ => Err(format!(
+                "unknown format '{}', expected one of: {}",
+                s,
+                NumberFormat::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NumberFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NumberFormat::Fraction => write!(f, "fraction"),
+            NumberFormat::Decimal => write!(f, "decimal"),
+            NumberFormat::Hex => write!(f, "hex"),
+            NumberFormat::Binary => write!(f, "binary"),
+            NumberFormat::Octal => write!(f, "octal"),
+            NumberFormat::Scientific => write!(f, "scientific"),
+            NumberFormat::Engineering => write!(f, "engineering"),
+            NumberFormat::All => write!(f, "all"),
+        }
+    }
+}
+
+impl Value {
+    /// Renders the value using `format`. Values the format doesn't apply
+    /// to, like fractions in hex, are shown as usual.
+    pub fn format(&self, format: NumberFormat) -> String {
+        if let Value::Quantity(quantity) = self {
+            let value = Value::Real(rounded(quantity.value));
+            return format!("{} {}", value.format(format), quantity.unit);
+        }
+        if let Value::Complex(val) = self {
+            return complex::format(val, format);
+        }
+        if let Value::Matrix(val) = self {
+            return val.render(|val| val.format(format));
+        }
+        let formatted = match format {
+            NumberFormat::Fraction => None,
+            NumberFormat::Decimal => match self {
+                Value::Rational(_) => Some(Value::Real(self.to_f64()).to_string()),
+                _ => None,
+            },
+            NumberFormat::Hex => self.to_radix(16),
+            NumberFormat::Binary => self.to_radix(2),
+            NumberFormat::Octal => self.to_radix(8),
+            NumberFormat::Scientific => self.to_exponent(1),
+            NumberFormat::Engineering => self.to_exponent(3),
+            NumberFormat::All => {
+                let formats: &[NumberFormat] = match self {
+                    _ if self.is_integer() => &[
+                        NumberFormat::Fraction,
+                        NumberFormat::Hex,
+                        NumberFormat::Octal,
+                        NumberFormat::Binary,
+                    ],
+                    Value::Rational(_) => &[NumberFormat::Fraction, NumberFormat::Decimal],
+                    _ => &[NumberFormat::Fraction],
+                };
+                let all: Vec<_> = formats.iter().map(|format| self.format(*format)).collect();
+                Some(all.join(" = "))
+            }
+        };
+        formatted.unwrap_or_else(|| self.to_string())
+    }
+
+    /// Integers with a `0x`, `0b` or `0o` prefix.
+    fn to_radix(&self, radix: u32) -> Option<String> {
+        let value = match self {
+            Value::Fixed(val, ty) if *val < 0 => BigInt::from(*val + (1 << ty.bits())),
+            val if val.is_integer() => val.to_bigint(),
+            _ => return None,
+        };
+        let prefix = match radix {
+            16 => "0x",
+            2 => "0b",
+            _ => "0o",
+        };
+        let sign = if value.is_negative() { "-" } else { "" };
+        Some(format!(
+            "{}{}{}",
+            sign,
+            prefix,
+            value.magnitude().to_str_radix(radix)
+        ))
+    }
+
+    /// Scientific notation with an exponent that's a multiple of `step`.
+    /// Integers keep all their digits, however large.
+    fn to_exponent(&self, step: i64) -> Option<String> {
+        let (sign, mut digits, exponent) = match self {
+            val if val.is_integer() => {
+                let val = val.to_bigint();
+                let digits = val.magnitude().to_string();
+                let exponent = digits.len() as i64 - 1;
+                let sign = if val.is_negative() { "-" } else { "" };
+                (sign, digits, exponent)
+            }
+            Value::Rational(_) | Value::Real(_) => {
+                // Rust's `{:e}` gives the shortest digits that round-trip
+                let formatted = format!("{:e}", self.to_f64().abs());
+                let (mantissa, exponent) = formatted.split_once('e')?;
+                let sign = if self.to_f64() < 0.0 { "-" } else { "" };
+                (sign, mantissa.replace('.', ""), exponent.parse().ok()?)
+            }
+            _ => return None,
+        };
+        let trimmed = digits.trim_end_matches('0').len().max(1);
+        digits.truncate(trimmed);
+        let shown = exponent - exponent.rem_euclid(step);
+        // Digits before the point
+        let whole = (exponent - shown + 1) as usize;
+        if digits.len() < whole {
+            digits.push_str(&"0".repeat(whole - digits.len()));
+        }
+        let (int, frac) = digits.split_at(whole);
+        let point = if frac.is_empty() { "" } else { "." };
+        Some(format!("{}{}{}{}e{}", sign, int, point, frac, shown))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntType;
+    use num_rational::BigRational;
+
+    #[test]
+    fn formats() {
+        let third = Value::Rational(BigRational::new(1.into(), 3.into()));
+        let test_table = vec![
+            (Value::Integer(255), NumberFormat::Hex, "0xff"),
+            (Value::Integer(-255), NumberFormat::Hex, "-0xff"),
+            (Value::Integer(5), NumberFormat::Binary, "0b101"),
+            (Value::Integer(8), NumberFormat::Octal, "0o10"),
+            (Value::Fixed(-1, IntType::I8), NumberFormat::Hex, "0xff"),
+            (
+                Value::Fixed(-2, IntType::I16),
+                NumberFormat::Binary,
+                "0b1111111111111110",
+            ),
+            (third.clone(), NumberFormat::Hex, "1/3"),
+            (Value::Integer(12345), NumberFormat::Scientific, "1.2345e4"),
+            (Value::Integer(12345), NumberFormat::Engineering, "12.345e3"),
+            (Value::Integer(100000), NumberFormat::Engineering, "100e3"),
+            (Value::Integer(-7), NumberFormat::Scientific, "-7e0"),
+            (Value::Integer(0), NumberFormat::Engineering, "0e0"),
+            (Value::Real(0.00012), NumberFormat::Engineering, "120e-6"),
+            (Value::Real(-0.5), NumberFormat::Scientific, "-5e-1"),
+            (
+                third.clone(),
+                NumberFormat::Engineering,
+                "333.3333333333333e-3",
+            ),
+            (
+                Value::BigInt(BigInt::from(3).pow(50)),
+                NumberFormat::Scientific,
+                "7.17897987691852588770249e23",
+            ),
+            (Value::Bool(true), NumberFormat::Scientific, "true"),
+            (
+                Value::Integer(10),
+                NumberFormat::All,
+                "10 = 0xa = 0o12 = 0b1010",
+            ),
+            (third, NumberFormat::All, "1/3 = 0.3333333333333333"),
+            (Value::Real(1.5), NumberFormat::All, "1.5"),
+        ];
+        for (value, format, expected) in test_table.into_iter() {
+            assert_eq!(value.format(format), expected, "{:?} {}", value, format);
+        }
+    }
+}
@@ -0,0 +1,638 @@
+use crate::complex::complex;
+use crate::env::check_interrupt;
+use crate::error::{EvalError, Span};
+use crate::matrix::{self, Matrix};
+use crate::value::{real, to_f64, MAX_POWER_BITS};
+use crate::{IntType, Op, OverflowMode, Value};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Signed, ToPrimitive};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// How many arguments a function accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    Range(usize, usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exact(n) => count == n,
+            Arity::Range(min, max) => (min..=max).contains(&count),
+            Arity::AtLeast(min) => count >= min,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        match *self {
+            Arity::Exact(n) => write!(f, "{} argument{}", n, plural(n)),
+            Arity::Range(min, max) => write!(f, "{} to {} arguments", min, max),
+            Arity::AtLeast(n) => write!(f, "at least {} argument{}", n, plural(n)),
+        }
+    }
+}
+
+/// What a built-in function accepts as arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    Real,
+    /// Real or complex numbers.
+    Complex,
+    Matrix,
+    /// Real numbers or matrices of them, which the function sees as one
+    /// list of numbers, so `sum(1, 2, 3)` and `sum([1, 2, 3])` agree.
+    List,
+}
+
+type BuiltinFn = fn(&str, Vec<Value>, Span) -> Result<Value, EvalError>;
+
+/// A function implemented in Rust and callable from expressions.
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub args: ArgKind,
+    func: BuiltinFn,
+}
+
+impl Builtin {
+    /// Calls the function. The caller is responsible for checking `arity`.
+    pub(crate) fn call(&self, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+        let args = match self.args {
+            ArgKind::List => args
+                .into_iter()
+                .flat_map(|arg| match arg {
+                    Value::Matrix(val) => val.into_elements(),
+                    val => vec![val],
+                })
+                .collect(),
+            _ => args,
+        };
+        for arg in &args {
+            match self.args {
+                ArgKind::Real | ArgKind::List => {
+                    arg.expect_real(span)?;
+                }
+                ArgKind::Complex => {
+                    arg.expect_number(span)?;
+                }
+                ArgKind::Matrix => {
+                    arg.expect_matrix(span)?;
+                }
+            }
+        }
+        (self.func)(self.name, args, span)
+    }
+}
+
+macro_rules! builtin {
+    ($name:literal, $arity:expr, $func:expr) => {
+        Builtin {
+            name: $name,
+            arity: $arity,
+            args: ArgKind::Real,
+            func: $func,
+        }
+    };
+    (complex $name:literal, $arity:expr, $func:expr) => {
+        Builtin {
+            name: $name,
+            arity: $arity,
+            args: ArgKind::Complex,
+            func: $func,
+        }
+    };
+    (matrix $name:literal, $arity:expr, $func:expr) => {
+        Builtin {
+            name: $name,
+            arity: $arity,
+            args: ArgKind::Matrix,
+            func: $func,
+        }
+    };
+    (list $name:literal, $arity:expr, $func:expr) => {
+        Builtin {
+            name: $name,
+            arity: $arity,
+            args: ArgKind::List,
+            func: $func,
+        }
+    };
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    builtin!(complex "sqrt", Arity::Exact(1), sqrt),
+    builtin!(complex "abs", Arity::Exact(1), abs),
+    builtin!(complex "re", Arity::Exact(1), re),
+    builtin!(complex "im", Arity::Exact(1), im),
+    builtin!(complex "conj", Arity::Exact(1), conj),
+    builtin!(complex "arg", Arity::Exact(1), arg),
+    builtin!("floor", Arity::Exact(1), |_, args, span| {
+        to_integer(&args[0], BigRational::floor, f64::floor, span)
+    }),
+    builtin!("ceil", Arity::Exact(1), |_, args, span| {
+        to_integer(&args[0], BigRational::ceil, f64::ceil, span)
+    }),
+    builtin!("round", Arity::Exact(1), |_, args, span| {
+        to_integer(&args[0], BigRational::round, f64::round, span)
+    }),
+    builtin!("sin", Arity::Exact(1), |_, args, span| {
+        real(args[0].to_f64().sin(), span)
+    }),
+    builtin!("cos", Arity::Exact(1), |_, args, span| {
+        real(args[0].to_f64().cos(), span)
+    }),
+    builtin!("tan", Arity::Exact(1), |_, args, span| {
+        real(args[0].to_f64().tan(), span)
+    }),
+    builtin!("ln", Arity::Exact(1), |_, args, span| {
+        logarithm(args[0].to_f64(), std::f64::consts::E, span)
+    }),
+    builtin!("log", Arity::Range(1, 2), |_, args, span| {
+        let base = args.get(1).map_or(10.0, Value::to_f64);
+        logarithm(args[0].to_f64(), base, span)
+    }),
+    builtin!("exp", Arity::Exact(1), |_, args, span| {
+        real(args[0].to_f64().exp(), span)
+    }),
+    builtin!("gcd", Arity::AtLeast(2), |name, args, span| {
+        let args = integer_args(name, &args, span)?;
+        Ok(Value::from(
+            args.iter().fold(BigInt::from(0), |acc, n| acc.gcd(n)),
+        ))
+    }),
+    builtin!("lcm", Arity::AtLeast(2), |name, args, span| {
+        let args = integer_args(name, &args, span)?;
+        Ok(Value::from(
+            args.iter().fold(BigInt::from(1), |acc, n| acc.lcm(n)),
+        ))
+    }),
+    builtin!("nCr", Arity::Exact(2), |name, args, span| {
+        count_selections(name, &args, true, span)
+    }),
+    builtin!("nPr", Arity::Exact(2), |name, args, span| {
+        count_selections(name, &args, false, span)
+    }),
+    // Builtins don't see the environment, so arithmetic on fixed-width
+    // elements uses the default overflow mode
+    builtin!(matrix "det", Arity::Exact(1), |_, args, span| {
+        matrix::determinant(args[0].expect_matrix(span)?, OverflowMode::default(), span)
+    }),
+    builtin!(matrix "inv", Arity::Exact(1), |_, args, span| {
+        let inverse = matrix::inverse(args[0].expect_matrix(span)?, OverflowMode::default(), span)?;
+        Ok(Value::Matrix(inverse))
+    }),
+    builtin!(matrix "transpose", Arity::Exact(1), |_, args, span| {
+        Ok(Value::Matrix(args[0].expect_matrix(span)?.transpose()))
+    }),
+    builtin!(matrix "dot", Arity::Exact(2), dot),
+    builtin!(matrix "cross", Arity::Exact(2), cross),
+    builtin!(matrix "solve", Arity::Exact(2), solve),
+    builtin!(list "min", Arity::AtLeast(1), |name, args, span| {
+        extreme(name, args, Ordering::Less, span)
+    }),
+    builtin!(list "max", Arity::AtLeast(1), |name, args, span| {
+        extreme(name, args, Ordering::Greater, span)
+    }),
+    builtin!(list "sum", Arity::AtLeast(1), |_, args, span| {
+        fold(args, Op::Add, Value::Integer(0), span)
+    }),
+    builtin!(list "product", Arity::AtLeast(1), product),
+    builtin!(list "count", Arity::AtLeast(1), |_, args, _| {
+        Ok(Value::Integer(args.len() as i64))
+    }),
+    builtin!(list "mean", Arity::AtLeast(1), mean),
+    builtin!(list "median", Arity::AtLeast(1), median),
+    builtin!(list "mode", Arity::AtLeast(1), mode),
+    builtin!(list "variance", Arity::AtLeast(1), variance),
+    builtin!(list "stdev", Arity::AtLeast(1), |name, args, span| {
+        sqrt(name, vec![variance(name, args, span)?], span)
+    }),
+    builtin!(list "percentile", Arity::AtLeast(2), percentile),
+    builtin!("i8", Arity::Exact(1), convert),
+    builtin!("i16", Arity::Exact(1), convert),
+    builtin!("i32", Arity::Exact(1), convert),
+    builtin!("i64", Arity::Exact(1), convert),
+    builtin!("u8", Arity::Exact(1), convert),
+    builtin!("u16", Arity::Exact(1), convert),
+    builtin!("u32", Arity::Exact(1), convert),
+    builtin!("u64", Arity::Exact(1), convert),
+];
+
+/// Finds the built-in function called `name`.
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+fn sqrt(_: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    let value = &args[0];
+    if let Value::Complex(val) = value {
+        return complex(val.sqrt(), span);
+    }
+    if value.to_f64() < 0.0 {
+        // The principal root of a negative number, so `sqrt(-4)` is `2i`
+        return complex(Complex64::new(0.0, (-value.to_f64()).sqrt()), span);
+    }
+    if value.is_exact() {
+        // Perfect squares, and fractions of them, stay exact
+        let (numer, denom) = value.to_rational().into();
+        let (root_numer, root_denom) = (numer.sqrt(), denom.sqrt());
+        if &root_numer * &root_numer == numer && &root_denom * &root_denom == denom {
+            return Ok(Value::from(BigRational::new(root_numer, root_denom)));
+        }
+    }
+    real(value.to_f64().sqrt(), span)
+}
+
+fn abs(_: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    Ok(match args.into_iter().next().unwrap() {
+        Value::Complex(val) => real(val.norm(), span)?,
+        Value::Real(val) => Value::Real(val.abs()),
+        Value::Rational(val) => Value::Rational(val.abs()),
+        val => Value::from(val.to_bigint().abs()),
+    })
+}
+
+fn re(_: &str, args: Vec<Value>, _: Span) -> Result<Value, EvalError> {
+    Ok(match args.into_iter().next().unwrap() {
+        Value::Complex(val) => Value::Real(val.re),
+        val => val,
+    })
+}
+
+fn im(_: &str, args: Vec<Value>, _: Span) -> Result<Value, EvalError> {
+    Ok(match &args[0] {
+        Value::Complex(val) => Value::Real(val.im),
+        _ => Value::Integer(0),
+    })
+}
+
+fn conj(_: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    match args.into_iter().next().unwrap() {
+        Value::Complex(val) => complex(val.conj(), span),
+        val => Ok(val),
+    }
+}
+
+/// The angle from the positive real axis, in radians.
+fn arg(_: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    real(args[0].to_complex().arg(), span)
+}
+
+/// The first argument that compares as `wanted` against every other one.
+fn extreme(name: &str, args: Vec<Value>, wanted: Ordering, span: Span) -> Result<Value, EvalError> {
+    args.into_iter()
+        .reduce(|best, next| {
+            if next.cmp_numeric(&best) == wanted {
+                next
+            } else {
+                best
+            }
+        })
+        .ok_or_else(|| too_few(name, 1, span))
+}
+
+/// Arithmetic on the elements of lists and vectors, with the default
+/// overflow mode as for the matrix functions.
+fn arith(lhs: Value, op: Op, rhs: Value, span: Span) -> Result<Value, EvalError> {
+    check_interrupt(span)?;
+    lhs.binary_op(&op, rhs, OverflowMode::default(), span)
+}
+
+fn fold(args: Vec<Value>, op: Op, init: Value, span: Span) -> Result<Value, EvalError> {
+    args.into_iter()
+        .try_fold(init, |acc, val| arith(acc, op.clone(), val, span))
+}
+
+fn too_few(name: &str, min: usize, span: Span) -> EvalError {
+    let plural = if min == 1 { "" } else { "s" };
+    EvalError::InvalidArgument {
+        name: name.to_string(),
+        reason: format!("expected at least {} value{}", min, plural),
+        span,
+    }
+}
+
+fn mean(name: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    if args.is_empty() {
+        return Err(too_few(name, 1, span));
+    }
+    let count = Value::Integer(args.len() as i64);
+    arith(
+        fold(args, Op::Add, Value::Integer(0), span)?,
+        Op::Divide,
+        count,
+        span,
+    )
+}
+
+fn sorted(mut args: Vec<Value>) -> Vec<Value> {
+    args.sort_by(Value::cmp_numeric);
+    args
+}
+
+/// Multiplies the values, giving up once the product is as big as the
+/// largest power `^` allows.
+fn product(_: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    // A product of nonzero integers has at least as many bits as its
+    // factors have after their leading ones, so very long products can be
+    // ruled out before working them out
+    if args
+        .iter()
+        .all(|val| matches!(val, Value::Integer(_) | Value::BigInt(_)))
+    {
+        let min_bits: Option<u64> = args
+            .iter()
+            .map(|val| val.to_bigint().bits().checked_sub(1))
+            .sum();
+        if min_bits.is_some_and(|bits| bits >= MAX_POWER_BITS) {
+            return Err(EvalError::Overflow { span });
+        }
+    }
+    args.into_iter().try_fold(Value::Integer(1), |acc, val| {
+        let product = arith(acc, Op::Multiply, val, span)?;
+        let bits = match &product {
+            Value::BigInt(val) => val.bits(),
+            Value::Rational(val) => val.numer().bits() + val.denom().bits(),
+            _ => 0,
+        };
+        if bits > MAX_POWER_BITS {
+            return Err(EvalError::Overflow { span });
+        }
+        Ok(product)
+    })
+}
+
+/// The middle value, or the mean of the middle two.
+fn median(name: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    if args.is_empty() {
+        return Err(too_few(name, 1, span));
+    }
+    let mut sorted = sorted(args);
+    let upper = sorted.split_off(sorted.len() / 2);
+    if sorted.len() == upper.len() {
+        mean(name, vec![sorted.pop().unwrap(), upper[0].clone()], span)
+    } else {
+        Ok(upper[0].clone())
+    }
+}
+
+/// The most common value. Of equally common values, the one that comes
+/// first wins.
+fn mode(name: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    // Sorting the positions brings equal values together, with the first of
+    // each at the start of its run
+    let mut order: Vec<usize> = (0..args.len()).collect();
+    order.sort_by(|&a, &b| args[a].cmp_numeric(&args[b]));
+    let mut best: Option<&[usize]> = None;
+    for run in order.chunk_by(|&a, &b| args[a].cmp_numeric(&args[b]).is_eq()) {
+        check_interrupt(span)?;
+        // The longest run, then the earliest first value
+        if best.is_none_or(|best| (run.len(), best[0]) > (best.len(), run[0])) {
+            best = Some(run);
+        }
+    }
+    best.map(|run| args[run[0]].clone())
+        .ok_or_else(|| too_few(name, 1, span))
+}
+
+/// The sample variance, dividing by one less than the number of values.
+fn variance(name: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    if args.len() < 2 {
+        return Err(too_few(name, 2, span));
+    }
+    let count = args.len() as i64;
+    let mean = mean(name, args.clone(), span)?;
+    let mut total = Value::Integer(0);
+    for val in args {
+        let deviation = arith(val, Op::Subtract, mean.clone(), span)?;
+        let square = arith(deviation.clone(), Op::Multiply, deviation, span)?;
+        total = arith(total, Op::Add, square, span)?;
+    }
+    arith(total, Op::Divide, Value::Integer(count - 1), span)
+}
+
+/// `percentile(values, p)`, interpolating between the two values nearest
+/// the `p`th percentile when it falls between them.
+fn percentile(name: &str, mut args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    let p = args.pop().unwrap();
+    if p.cmp_numeric(&Value::Integer(0)).is_lt() || p.cmp_numeric(&Value::Integer(100)).is_gt() {
+        return Err(EvalError::InvalidArgument {
+            name: name.to_string(),
+            reason: "percentile must be between 0 and 100".to_string(),
+            span,
+        });
+    }
+    if args.is_empty() {
+        return Err(too_few(name, 1, span));
+    }
+    let sorted = sorted(args);
+    let last = Value::Integer(sorted.len() as i64 - 1);
+    let rank = arith(
+        arith(p, Op::Multiply, last, span)?,
+        Op::Divide,
+        Value::Integer(100),
+        span,
+    )?;
+    let lower = to_integer(&rank, BigRational::floor, f64::floor, span)?;
+    let fraction = arith(rank, Op::Subtract, lower.clone(), span)?;
+    let index = lower.to_bigint().to_usize().unwrap_or(0);
+    match sorted.get(index + 1) {
+        Some(next) => {
+            let gap = arith(next.clone(), Op::Subtract, sorted[index].clone(), span)?;
+            let offset = arith(fraction, Op::Multiply, gap, span)?;
+            arith(sorted[index].clone(), Op::Add, offset, span)
+        }
+        None => Ok(sorted[index].clone()),
+    }
+}
+
+fn to_integer(
+    value: &Value,
+    exact: fn(&BigRational) -> BigRational,
+    approx: fn(f64) -> f64,
+    span: Span,
+) -> Result<Value, EvalError> {
+    match value {
+        Value::Real(val) => BigInt::from_f64(approx(*val))
+            .map(Value::from)
+            .ok_or(EvalError::Overflow { span }),
+        val => Ok(Value::from(exact(&val.to_rational()))),
+    }
+}
+
+fn logarithm(value: f64, base: f64, span: Span) -> Result<Value, EvalError> {
+    if value <= 0.0 || base <= 0.0 || base == 1.0 {
+        return Err(EvalError::Domain { span });
+    }
+    // The dedicated functions are exact for powers of their base
+    let result = match base {
+        2.0 => value.log2(),
+        10.0 => value.log10(),
+        std::f64::consts::E => value.ln(),
+        _ => value.log(base),
+    };
+    real(result, span)
+}
+
+/// The elements of each argument, which must be vectors of the same length.
+fn vectors<'a>(name: &str, args: &'a [Value], span: Span) -> Result<Vec<&'a [Value]>, EvalError> {
+    let mut vectors: Vec<&[Value]> = Vec::new();
+    for arg in args {
+        let vector = arg.expect_matrix(span)?;
+        if !vector.is_vector() {
+            return Err(EvalError::InvalidArgument {
+                name: name.to_string(),
+                reason: format!("expected vectors, found a {} matrix", vector.shape()),
+                span,
+            });
+        }
+        if let Some(first) = vectors.first() {
+            if first.len() != vector.elements().len() {
+                return Err(EvalError::ShapeMismatch {
+                    lhs: args[0].expect_matrix(span)?.shape(),
+                    rhs: vector.shape(),
+                    span,
+                });
+            }
+        }
+        vectors.push(vector.elements());
+    }
+    Ok(vectors)
+}
+
+fn dot(name: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    let vectors = vectors(name, &args, span)?;
+    vectors[0]
+        .iter()
+        .zip(vectors[1])
+        .try_fold(Value::Integer(0), |sum, (lhs, rhs)| {
+            let term = arith(lhs.clone(), Op::Multiply, rhs.clone(), span)?;
+            arith(sum, Op::Add, term, span)
+        })
+}
+
+/// The cross product of two vectors of length 3, shaped like the first.
+fn cross(name: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    let vectors = vectors(name, &args, span)?;
+    let (u, v) = (vectors[0], vectors[1]);
+    if u.len() != 3 {
+        return Err(EvalError::InvalidArgument {
+            name: name.to_string(),
+            reason: "expected vectors of length 3".to_string(),
+            span,
+        });
+    }
+    let term = |a: usize, b: usize| -> Result<Value, EvalError> {
+        let lhs = arith(u[a].clone(), Op::Multiply, v[b].clone(), span)?;
+        let rhs = arith(u[b].clone(), Op::Multiply, v[a].clone(), span)?;
+        arith(lhs, Op::Subtract, rhs, span)
+    };
+    let elements = vec![term(1, 2)?, term(2, 0)?, term(0, 1)?];
+    let shape = args[0].expect_matrix(span)?;
+    Ok(Value::Matrix(Matrix::new(
+        shape.rows(),
+        shape.cols(),
+        elements,
+    )))
+}
+
+/// Solves `A * x = b`. A row vector `b` is treated as a column, and the
+/// solution has the same shape as `b`.
+fn solve(_: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    let (lhs, rhs) = (args[0].expect_matrix(span)?, args[1].expect_matrix(span)?);
+    let mode = OverflowMode::default();
+    if rhs.rows() == 1 && rhs.cols() > 1 {
+        let solution = matrix::solve(lhs, &rhs.transpose(), mode, span)?;
+        return Ok(Value::Matrix(solution.transpose()));
+    }
+    Ok(Value::Matrix(matrix::solve(lhs, rhs, mode, span)?))
+}
+
+/// `nCr(n, r)` counts the ways to choose `r` of `n` items, and `nPr(n, r)`
+/// the ways to arrange them. Both are zero when `r` is larger than `n`.
+fn count_selections(
+    name: &str,
+    args: &[Value],
+    combinations: bool,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let args = integer_args(name, args, span)?;
+    let (n, r) = (&args[0], &args[1]);
+    if n.is_negative() || r.is_negative() {
+        return Err(EvalError::InvalidArgument {
+            name: name.to_string(),
+            reason: "expected non-negative integers".to_string(),
+            span,
+        });
+    }
+    if r > n {
+        return Ok(Value::Integer(0));
+    }
+    // Choosing r items is the same as leaving out the other n - r
+    let r = if combinations {
+        r.min(&(n - r)).clone()
+    } else {
+        r.clone()
+    };
+    // Lower bounds on the size of the result, so hopelessly large ones fail
+    // early: nCr is at least (n/r)^r, and the larger half of the factors of
+    // nPr are each at least n - r/2
+    let (n_approx, r_approx) = (to_f64(n), to_f64(&r));
+    let min_bits = if combinations {
+        r_approx * (n_approx / r_approx).log2()
+    } else {
+        r_approx / 2.0 * (n_approx - r_approx / 2.0).log2()
+    };
+    if min_bits > MAX_POWER_BITS as f64 {
+        return Err(EvalError::Overflow { span });
+    }
+    let mut result = BigInt::from(1);
+    let mut i = BigInt::from(0);
+    while i < r {
+        check_interrupt(span)?;
+        result *= n - &i;
+        i += 1;
+        if combinations {
+            // Exact, since any i consecutive integers have i! as a factor
+            result /= &i;
+        }
+        if result.bits() > MAX_POWER_BITS {
+            return Err(EvalError::Overflow { span });
+        }
+    }
+    Ok(Value::from(result))
+}
+
+/// Converts an integer to the fixed-width type the function is named after.
+/// Like a cast, this keeps the low bits whatever the overflow mode, so
+/// `u8(-1)` is 255.
+fn convert(name: &str, args: Vec<Value>, span: Span) -> Result<Value, EvalError> {
+    let ty = IntType::from_name(name).ok_or_else(|| EvalError::UnknownFunction {
+        name: name.to_string(),
+        span,
+    })?;
+    let value = &integer_args(name, &args, span)?[0];
+    Ok(Value::Fixed(ty.wrap(value), ty))
+}
+
+fn integer_args(name: &str, args: &[Value], span: Span) -> Result<Vec<BigInt>, EvalError> {
+    args.iter()
+        .map(|arg| match arg {
+            _ if arg.is_integer() => Ok(arg.to_bigint()),
+            _ => Err(EvalError::InvalidArgument {
+                name: name.to_string(),
+                reason: "expected integers".to_string(),
+                span,
+            }),
+        })
+        .collect()
+}
@@ -1,9 +1,37 @@
-use pest::iterators::Pairs;
+use complex::complex;
+use env::Scope;
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use pest::iterators::{Pair, Pairs};
 use pest::pratt_parser::PrattParser;
 use pest::Parser;
-use std::convert::TryInto;
-use std::io;
-use std::io::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+
+mod complex;
+mod constants;
+mod editor;
+mod env;
+mod error;
+mod fixed;
+mod format;
+mod functions;
+mod matrix;
+mod repl;
+mod tree;
+mod units;
+mod value;
+
+pub use constants::{Constant, CONSTANTS};
+pub use env::{Environment, Function, MAX_CALL_DEPTH};
+pub use error::{EvalError, ParseError, ParseErrorKind, Span};
+pub use fixed::{IntType, OverflowMode};
+pub use format::NumberFormat;
+pub use functions::{ArgKind, Arity, Builtin, BUILTINS};
+pub use matrix::Matrix;
+pub use repl::{repl, Session};
+pub use units::{Dimension, Quantity, Unit, UnitDef, UNITS};
+pub use value::Value;
 
 #[derive(pest_derive::Parser)]
 #[grammar = "calc.pest"]
@@ -16,15 +44,40 @@ lazy_static::lazy_static! {
 
         // Precedence is defined lowest to highest
         PrattParser::new()
+            .op(Op::postfix(conversion))
+            .op(Op::infix(or, Left))
+            .op(Op::infix(and, Left))
+            .op(Op::infix(eq, Left)
+                | Op::infix(ne, Left)
+                | Op::infix(lt, Left)
+                | Op::infix(le, Left)
+                | Op::infix(gt, Left)
+                | Op::infix(ge, Left))
+            // `1..n+1 == xs` compares the whole range
+            .op(Op::infix(range, Left))
+            // Bitwise operators bind tighter than comparisons, as in Python,
+            // so `x & 0xf == 0` tests the masked bits
+            .op(Op::infix(bit_or, Left))
+            .op(Op::infix(xor, Left))
+            .op(Op::infix(bit_and, Left))
+            .op(Op::infix(shift_left, Left) | Op::infix(shift_right, Left))
             // Addition and subtract have equal precedence
             .op(Op::infix(add, Left) | Op::infix(subtract, Left))
             .op(Op::infix(multiply, Left) | Op::infix(divide, Left) | Op::infix(modulo, Left))
-            .op(Op::infix(power, Left))
-            .op(Op::prefix(unary_minus))
+            // Implicit multiplication binds tighter than `*` and `/`, so
+            // 1/2(3) is 1/6, just as 1/2x is 1/(2x)
+            .op(Op::infix(implicit, Left))
+            // Prefix minus binds looser than power, as in written maths:
+            // -2^2 is -(2^2), while 2^-2 still negates the exponent
+            .op(Op::prefix(unary_minus) | Op::prefix(not) | Op::prefix(bit_not))
+            // Power is right associative: 2^3^2 is 2^(3^2)
+            .op(Op::infix(power, Right))
+            // Postfix operators bind tightest, so 2^3! is 2^(3!) and -3! is -(3!)
+            .op(Op::postfix(factorial) | Op::postfix(percent) | Op::postfix(degree))
     };
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Op {
     Add,
     Subtract,
@@ -32,106 +85,1105 @@ pub enum Op {
     Divide,
     Modulo,
     Power,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    /// `&&`, which only evaluates its right side when the left is true
+    And,
+    /// `||`, which only evaluates its right side when the left is false
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    /// `a..b`, the integers from a to b inclusive
+    Range,
 }
 
-#[derive(Debug)]
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Op::Add => "+",
+            Op::Subtract => "-",
+            Op::Multiply => "*",
+            Op::Divide => "/",
+            Op::Modulo => "%",
+            Op::Power => "^",
+            Op::Equal => "==",
+            Op::NotEqual => "!=",
+            Op::Less => "<",
+            Op::LessEqual => "<=",
+            Op::Greater => ">",
+            Op::GreaterEqual => ">=",
+            Op::And => "&&",
+            Op::Or => "||",
+            Op::BitAnd => "&",
+            Op::BitOr => "|",
+            Op::BitXor => "xor",
+            Op::ShiftLeft => "<<",
+            Op::ShiftRight => ">>",
+            Op::Range => "..",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Expr {
-    Integer(i32),
-    UnaryMinus(Box<Expr>),
+    Integer {
+        value: BigInt,
+        span: Span,
+    },
+    Real {
+        value: f64,
+        span: Span,
+    },
+    Bool {
+        value: bool,
+        span: Span,
+    },
+    /// An imaginary literal like `2i`, holding the coefficient of `i`
+    Imaginary {
+        value: f64,
+        span: Span,
+    },
+    /// A number literal with a unit, like `3 km`
+    Quantity {
+        value: f64,
+        unit: Unit,
+        span: Span,
+    },
+    /// A quantity whose unit names could also be variables, like `2 m`.
+    /// It's the product of the number and the variables if any of them are
+    /// defined, and the quantity otherwise
+    Juxtaposed {
+        quantity: Box<Expr>,
+        product: Box<Expr>,
+        span: Span,
+    },
+    Variable {
+        name: String,
+        span: Span,
+    },
+    /// `$n`, the `n`th recorded result
+    History {
+        index: usize,
+        span: Span,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+        span: Span,
+    },
+    UnaryMinus {
+        expr: Box<Expr>,
+        span: Span,
+    },
+    Not {
+        expr: Box<Expr>,
+        span: Span,
+    },
+    /// `~x`, bitwise complement
+    BitNot {
+        expr: Box<Expr>,
+        span: Span,
+    },
+    /// A matrix literal like `[1, 2; 3, 4]`, with its elements row by row
+    Matrix {
+        rows: usize,
+        cols: usize,
+        elements: Vec<Expr>,
+        span: Span,
+    },
+    /// `n!`
+    Factorial {
+        expr: Box<Expr>,
+        span: Span,
+    },
+    /// `x%`, a hundredth of x
+    Percent {
+        expr: Box<Expr>,
+        span: Span,
+    },
+    /// `x°`, an angle in degrees converted to radians
+    Degrees {
+        expr: Box<Expr>,
+        span: Span,
+    },
+    /// `expr to unit`
+    Convert {
+        expr: Box<Expr>,
+        unit: Unit,
+        span: Span,
+    },
+    /// `if condition then a else b`
+    If {
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+        span: Span,
+    },
     BinOp {
         lhs: Box<Expr>,
         op: Op,
         rhs: Box<Expr>,
+        span: Span,
+    },
+    /// Left-associative operations in a row, like `1 + 2 - 3`, applied in
+    /// turn to `first`. Kept flat so a long sum doesn't nest deeply
+    Chain {
+        first: Box<Expr>,
+        rest: Vec<(Op, Expr)>,
+        span: Span,
     },
 }
 
 impl Expr {
-    pub fn eval(&self) -> i32 {
+    /// Source range covered by this expression.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Integer { span, .. }
+            | Expr::Real { span, .. }
+            | Expr::Bool { span, .. }
+            | Expr::Imaginary { span, .. }
+            | Expr::Quantity { span, .. }
+            | Expr::Juxtaposed { span, .. }
+            | Expr::Matrix { span, .. }
+            | Expr::Convert { span, .. }
+            | Expr::Variable { span, .. }
+            | Expr::History { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::UnaryMinus { span, .. }
+            | Expr::Not { span, .. }
+            | Expr::BitNot { span, .. }
+            | Expr::Factorial { span, .. }
+            | Expr::Percent { span, .. }
+            | Expr::Degrees { span, .. }
+            | Expr::If { span, .. }
+            | Expr::BinOp { span, .. }
+            | Expr::Chain { span, .. } => *span,
+        }
+    }
+
+    pub fn eval(&self, env: &Environment) -> Result<Value, EvalError> {
+        let _guard = env.watch_interrupts();
+        self.eval_in(&Scope::global(env))
+    }
+
+    // Each arm delegates to a separate function so that the frame of this
+    // one, which every level of recursion goes through, stays small.
+    fn eval_in(&self, scope: &Scope) -> Result<Value, EvalError> {
+        if scope.env.interrupted() {
+            return Err(EvalError::Interrupted { span: self.span() });
+        }
         match self {
-            Expr::Integer(val) => *val as i32,
-            Expr::UnaryMinus(val) => val.eval() - val.eval() * 2,
-            Expr::BinOp { lhs, op, rhs } => match op {
-                Op::Add => lhs.eval() + rhs.eval(),
-                Op::Subtract => lhs.eval() - rhs.eval(),
-                Op::Multiply => lhs.eval() * rhs.eval(),
-                Op::Divide => lhs.eval() / rhs.eval(),
-                Op::Modulo => lhs.eval() % rhs.eval(),
-                Op::Power => lhs.eval().pow(rhs.eval().try_into().unwrap()),
+            Expr::Integer { value, .. } => Ok(Value::from(value.clone())),
+            Expr::Real { value, .. } => Ok(Value::Real(*value)),
+            Expr::Bool { value, .. } => Ok(Value::Bool(*value)),
+            Expr::Imaginary { value, span } => complex(Complex64::new(0.0, *value), *span),
+            Expr::Quantity { value, unit, span } => Quantity {
+                value: *value,
+                unit: unit.clone(),
+            }
+            .into_value(*span),
+            Expr::Juxtaposed {
+                quantity, product, ..
+            } => juxtaposed(quantity, product, scope),
+            Expr::Matrix {
+                rows,
+                cols,
+                elements,
+                ..
+            } => matrix(*rows, *cols, elements, scope),
+            Expr::Convert { expr, unit, span } => convert(expr, unit, *span, scope),
+            Expr::Variable { name, span } => lookup(name, *span, scope),
+            Expr::History { index, span } => match scope.env.result(*index) {
+                Some(value) => Ok(value.clone()),
+                None => Err(EvalError::NoSuchResult {
+                    index: *index,
+                    span: *span,
+                }),
             },
+            Expr::Call { name, args, span } => call(name, args, *span, scope),
+            Expr::UnaryMinus { expr, span } => negate(expr, *span, scope),
+            Expr::Not { expr, .. } => not(expr, scope),
+            Expr::BitNot { expr, span } => bit_not(expr, *span, scope),
+            Expr::Factorial { expr, span } => factorial(expr, *span, scope),
+            Expr::Percent { expr, span } => {
+                scale(expr, Value::Integer(100), Op::Divide, *span, scope)
+            }
+            Expr::Degrees { expr, span } => {
+                let radians = Value::Real(std::f64::consts::PI / 180.0);
+                scale(expr, radians, Op::Multiply, *span, scope)
+            }
+            Expr::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => conditional(condition, then_branch, else_branch, scope),
+            Expr::BinOp { lhs, op, rhs, span } => binary(lhs, op, rhs, *span, scope),
+            Expr::Chain { first, rest, .. } => chain(first, rest, scope),
+        }
+    }
+}
+
+fn lookup(name: &str, span: Span, scope: &Scope) -> Result<Value, EvalError> {
+    if let Some(value) = scope.lookup(name) {
+        return Ok(value.clone());
+    }
+    // The previous result, unless a variable has taken the name
+    if let ("ans" | "_", Some(value)) = (name, scope.env.last_result()) {
+        return Ok(value.clone());
+    }
+    if let Some(constant) = constants::lookup(name) {
+        return Ok(constant.value());
+    }
+    // A unit on its own is one of it, so `2^3 m` is 8 m
+    match Unit::named(name) {
+        Some(unit) => Quantity { value: 1.0, unit }.into_value(span),
+        None => Err(EvalError::UndefinedVariable {
+            name: name.to_string(),
+            span,
+        }),
+    }
+}
+
+fn juxtaposed(quantity: &Expr, product: &Expr, scope: &Scope) -> Result<Value, EvalError> {
+    if uses_variable(product, scope) {
+        product.eval_in(scope)
+    } else {
+        quantity.eval_in(scope)
+    }
+}
+
+/// Whether a product built by `implicit_product` names a variable in scope.
+fn uses_variable(expr: &Expr, scope: &Scope) -> bool {
+    match expr {
+        Expr::Variable { name, .. } => scope.lookup(name).is_some(),
+        Expr::BinOp { lhs, rhs, .. } => uses_variable(lhs, scope) || uses_variable(rhs, scope),
+        _ => false,
+    }
+}
+
+fn matrix(rows: usize, cols: usize, elements: &[Expr], scope: &Scope) -> Result<Value, EvalError> {
+    let elements = elements
+        .iter()
+        .map(|element| {
+            let value = element.eval_in(scope)?;
+            value.expect_number(element.span())?;
+            Ok(value)
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Value::Matrix(Matrix::new(rows, cols, elements)))
+}
+
+fn negate(expr: &Expr, span: Span, scope: &Scope) -> Result<Value, EvalError> {
+    expr.eval_in(scope)?.negate(scope.env.overflow_mode(), span)
+}
+
+fn not(expr: &Expr, scope: &Scope) -> Result<Value, EvalError> {
+    Ok(Value::Bool(!expr.eval_in(scope)?.as_bool(expr.span())?))
+}
+
+fn convert(expr: &Expr, unit: &Unit, span: Span, scope: &Scope) -> Result<Value, EvalError> {
+    units::convert(expr.eval_in(scope)?, unit, span)
+}
+
+fn bit_not(expr: &Expr, span: Span, scope: &Scope) -> Result<Value, EvalError> {
+    expr.eval_in(scope)?.bit_not(span)
+}
+
+fn factorial(expr: &Expr, span: Span, scope: &Scope) -> Result<Value, EvalError> {
+    expr.eval_in(scope)?
+        .factorial(scope.env.overflow_mode(), span)
+}
+
+/// Applies `op` with a constant right operand, for `%` and `°`.
+fn scale(expr: &Expr, by: Value, op: Op, span: Span, scope: &Scope) -> Result<Value, EvalError> {
+    expr.eval_in(scope)?
+        .binary_op(&op, by, scope.env.overflow_mode(), span)
+}
+
+fn conditional(
+    condition: &Expr,
+    then_branch: &Expr,
+    else_branch: &Expr,
+    scope: &Scope,
+) -> Result<Value, EvalError> {
+    if condition.eval_in(scope)?.as_bool(condition.span())? {
+        then_branch.eval_in(scope)
+    } else {
+        else_branch.eval_in(scope)
+    }
+}
+
+fn binary(lhs: &Expr, op: &Op, rhs: &Expr, span: Span, scope: &Scope) -> Result<Value, EvalError> {
+    apply(lhs.eval_in(scope)?, lhs.span(), op, rhs, span, scope)
+}
+
+/// Evaluates each operation of a chain in turn, in a loop rather than
+/// recursing once per operation.
+fn chain(first: &Expr, rest: &[(Op, Expr)], scope: &Scope) -> Result<Value, EvalError> {
+    let mut value = first.eval_in(scope)?;
+    let mut span = first.span();
+    for (op, rhs) in rest {
+        let lhs_span = span;
+        span = span.to(rhs.span());
+        value = apply(value, lhs_span, op, rhs, span, scope)?;
+    }
+    Ok(value)
+}
+
+/// Applies `op` to the value of its left side, which came from `lhs_span`,
+/// and its right side.
+fn apply(
+    lhs: Value,
+    lhs_span: Span,
+    op: &Op,
+    rhs: &Expr,
+    span: Span,
+    scope: &Scope,
+) -> Result<Value, EvalError> {
+    if let Op::And | Op::Or = op {
+        let lhs = lhs.as_bool(lhs_span)?;
+        // Short-circuit: the right side may be an error or unbounded
+        // recursion guarded by the left
+        if lhs == matches!(op, Op::Or) {
+            return Ok(Value::Bool(lhs));
+        }
+        return Ok(Value::Bool(rhs.eval_in(scope)?.as_bool(rhs.span())?));
+    }
+    let mode = scope.env.overflow_mode();
+    lhs.binary_op(op, rhs.eval_in(scope)?, mode, span)
+}
+
+/// Evaluates a call to a user-defined or built-in function. Kept out of
+/// `eval_in` so recursive calls use as little stack per level as possible.
+fn call(name: &str, args: &[Expr], span: Span, scope: &Scope) -> Result<Value, EvalError> {
+    if let Some(function) = scope.env.function(name) {
+        let args = eval_args(name, Arity::Exact(function.params.len()), args, span, scope)?;
+        if scope.depth >= MAX_CALL_DEPTH {
+            return Err(EvalError::RecursionLimit {
+                name: name.to_string(),
+                span,
+            });
+        }
+        // The body's spans point into the line that defined it, so report
+        // its errors at the call instead
+        return function
+            .body
+            .eval_in(&scope.enter(function, args))
+            .map_err(|e| e.with_span(span));
+    }
+    let builtin = functions::lookup(name).ok_or_else(|| EvalError::UnknownFunction {
+        name: name.to_string(),
+        span,
+    })?;
+    let args = eval_args(name, builtin.arity, args, span, scope)?;
+    builtin.call(args, span)
+}
+
+/// Checks the argument count against `arity`, then evaluates the arguments.
+fn eval_args(
+    name: &str,
+    arity: Arity,
+    args: &[Expr],
+    span: Span,
+    scope: &Scope,
+) -> Result<Vec<Value>, EvalError> {
+    if !arity.accepts(args.len()) {
+        return Err(EvalError::ArityMismatch {
+            name: name.to_string(),
+            expected: arity,
+            found: args.len(),
+            span,
+        });
+    }
+    args.iter().map(|arg| arg.eval_in(scope)).collect()
+}
+
+/// A complete line of input.
+#[derive(Debug)]
+pub enum Statement {
+    /// `name = expr`
+    Assign {
+        name: String,
+        expr: Expr,
+    },
+    /// `name(params) = body`
+    Define {
+        name: String,
+        function: Function,
+    },
+    Expr(Expr),
+    /// `statement in format`, showing the result in `format` rather than
+    /// the session's usual one.
+    Display {
+        statement: Box<Statement>,
+        format: NumberFormat,
+    },
+}
+
+impl Statement {
+    /// Evaluates the statement, storing any binding it makes in `env`.
+    /// Function definitions have no value.
+    pub fn execute(&self, env: &mut Environment) -> Result<Option<Value>, EvalError> {
+        match self {
+            Statement::Assign { name, expr } => {
+                let value = expr.eval(env)?;
+                env.set(name, value.clone());
+                Ok(Some(value))
+            }
+            Statement::Define { name, function } => {
+                env.define(name, function.clone());
+                Ok(None)
+            }
+            Statement::Expr(expr) => expr.eval(env).map(Some),
+            Statement::Display { statement, .. } => statement.execute(env),
         }
     }
 }
 
-pub fn parse_expr(pairs: Pairs<Rule>) -> Expr {
+pub fn parse_expr(pairs: Pairs<Rule>, syntax: Syntax) -> Result<Expr, ParseError> {
     PRATT_PARSER
         .map_primary(|primary| match primary.as_rule() {
-            Rule::integer => Expr::Integer(primary.as_str().parse::<i32>().unwrap()),
-            Rule::expr => parse_expr(primary.into_inner()),
-            rule => unreachable!("Expr::parse expected atom, found {:?}", rule),
+            Rule::integer => match primary.as_str().parse::<BigInt>() {
+                Ok(value) => Ok(Expr::Integer {
+                    value,
+                    span: primary.as_span().into(),
+                }),
+                Err(_) => Err(ParseError::from_span(
+                    ParseErrorKind::LiteralOutOfRange {
+                        literal: primary.as_str().to_string(),
+                    },
+                    primary.as_span(),
+                )),
+            },
+            Rule::hex | Rule::binary | Rule::octal => {
+                let radix = match primary.as_rule() {
+                    Rule::hex => 16,
+                    Rule::binary => 2,
+                    _ => 8,
+                };
+                // Skip the 0x, 0b or 0o prefix
+                match BigInt::parse_bytes(&primary.as_str().as_bytes()[2..], radix) {
+                    Some(value) => Ok(Expr::Integer {
+                        value,
+                        span: primary.as_span().into(),
+                    }),
+                    None => Err(unsupported(primary.as_span())),
+                }
+            }
+            Rule::quantity => {
+                let span = primary.as_span().into();
+                let mut inner = primary.into_inner();
+                let number = inner.next().unwrap();
+                let value = match number.as_str().parse::<f64>() {
+                    Ok(value) if value.is_finite() => value,
+                    _ => {
+                        return Err(ParseError::from_span(
+                            ParseErrorKind::LiteralOutOfRange {
+                                literal: number.as_str().to_string(),
+                            },
+                            number.as_span(),
+                        ))
+                    }
+                };
+                let unit_expr = inner.next().unwrap();
+                match parse_unit(unit_expr.clone()) {
+                    // With implicit multiplication, a variable can take a
+                    // unit's name, so which it is waits until evaluation
+                    Ok(unit) if syntax == Syntax::Relaxed => {
+                        let coefficient = parse_expr(Pairs::single(number), syntax)?;
+                        Ok(Expr::Juxtaposed {
+                            quantity: Box::new(Expr::Quantity { value, unit, span }),
+                            product: Box::new(implicit_product(coefficient, unit_expr)),
+                            span,
+                        })
+                    }
+                    Ok(unit) => Ok(Expr::Quantity { value, unit, span }),
+                    Err(ParseError {
+                        kind: ParseErrorKind::UnknownUnit { .. },
+                        ..
+                    }) if syntax == Syntax::Relaxed => {
+                        let coefficient = parse_expr(Pairs::single(number), syntax)?;
+                        Ok(implicit_product(coefficient, unit_expr))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Rule::imaginary => {
+                let literal = primary.as_str().trim_end_matches('i');
+                match literal.parse::<f64>() {
+                    Ok(value) if value.is_finite() => Ok(Expr::Imaginary {
+                        value,
+                        span: primary.as_span().into(),
+                    }),
+                    _ => Err(ParseError::from_span(
+                        ParseErrorKind::LiteralOutOfRange {
+                            literal: primary.as_str().to_string(),
+                        },
+                        primary.as_span(),
+                    )),
+                }
+            }
+            Rule::decimal => match primary.as_str().parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(Expr::Real {
+                    value,
+                    span: primary.as_span().into(),
+                }),
+                _ => Err(ParseError::from_span(
+                    ParseErrorKind::LiteralOutOfRange {
+                        literal: primary.as_str().to_string(),
+                    },
+                    primary.as_span(),
+                )),
+            },
+            Rule::boolean => Ok(Expr::Bool {
+                value: primary.as_str() == "true",
+                span: primary.as_span().into(),
+            }),
+            Rule::conditional => {
+                let span = primary.as_span().into();
+                let mut branches = primary
+                    .into_inner()
+                    .filter(|pair| pair.as_rule() == Rule::expr)
+                    .map(|pair| parse_expr(pair.into_inner(), syntax).map(Box::new));
+                Ok(Expr::If {
+                    condition: branches.next().unwrap()?,
+                    then_branch: branches.next().unwrap()?,
+                    else_branch: branches.next().unwrap()?,
+                    span,
+                })
+            }
+            Rule::ident => Ok(Expr::Variable {
+                name: primary.as_str().to_string(),
+                span: primary.as_span().into(),
+            }),
+            Rule::history_ref => match primary.as_str()[1..].parse() {
+                Ok(index) => Ok(Expr::History {
+                    index,
+                    span: primary.as_span().into(),
+                }),
+                Err(_) => Err(ParseError::from_span(
+                    ParseErrorKind::LiteralOutOfRange {
+                        literal: primary.as_str().to_string(),
+                    },
+                    primary.as_span(),
+                )),
+            },
+            Rule::call => {
+                let span = primary.as_span().into();
+                let mut inner = primary.into_inner();
+                let name = inner.next().unwrap().as_str().to_string();
+                let args = inner
+                    .map(|arg| parse_expr(arg.into_inner(), syntax))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Expr::Call { name, args, span })
+            }
+            Rule::matrix => {
+                let span = primary.as_span();
+                let rows = primary
+                    .into_inner()
+                    .map(|row| {
+                        row.into_inner()
+                            .map(|expr| parse_expr(expr.into_inner(), syntax))
+                            .collect()
+                    })
+                    .collect::<Result<Vec<Vec<_>>, _>>()?;
+                let cols = rows.first().map_or(0, Vec::len);
+                if rows.iter().any(|row| row.len() != cols) {
+                    return Err(ParseError::from_span(ParseErrorKind::RaggedMatrix, span));
+                }
+                Ok(Expr::Matrix {
+                    rows: rows.len(),
+                    cols,
+                    elements: rows.into_iter().flatten().collect(),
+                    span: span.into(),
+                })
+            }
+            Rule::expr => parse_expr(primary.into_inner(), syntax),
+            _ => Err(unsupported(primary.as_span())),
         })
         .map_infix(|lhs, op, rhs| {
+            let (lhs, rhs) = (lhs?, rhs?);
             let op = match op.as_rule() {
+                Rule::implicit if syntax == Syntax::Strict => {
+                    return Err(ParseError::from_span(
+                        ParseErrorKind::ImplicitMultiplication,
+                        op.as_span(),
+                    ));
+                }
+                Rule::implicit => Op::Multiply,
                 Rule::add => Op::Add,
                 Rule::subtract => Op::Subtract,
                 Rule::multiply => Op::Multiply,
                 Rule::divide => Op::Divide,
                 Rule::modulo => Op::Modulo,
                 Rule::power => Op::Power,
-                rule => unreachable!("Expr::parse expected infix operation, found {:?}", rule),
+                Rule::eq => Op::Equal,
+                Rule::ne => Op::NotEqual,
+                Rule::lt => Op::Less,
+                Rule::le => Op::LessEqual,
+                Rule::gt => Op::Greater,
+                Rule::ge => Op::GreaterEqual,
+                Rule::and => Op::And,
+                Rule::or => Op::Or,
+                Rule::bit_and => Op::BitAnd,
+                Rule::bit_or => Op::BitOr,
+                Rule::xor => Op::BitXor,
+                Rule::shift_left => Op::ShiftLeft,
+                Rule::shift_right => Op::ShiftRight,
+                Rule::range => Op::Range,
+                _ => return Err(unsupported(op.as_span())),
             };
-            Expr::BinOp {
-                lhs: Box::new(lhs),
-                op,
-                rhs: Box::new(rhs),
+            let span = lhs.span().to(rhs.span());
+            // Everything but `^` groups to the left, so another operation
+            // after one extends a chain rather than nesting
+            let left_assoc = |op: &Op| !matches!(op, Op::Power);
+            Ok(match lhs {
+                Expr::Chain {
+                    first, mut rest, ..
+                } if left_assoc(&op) => {
+                    rest.push((op, rhs));
+                    Expr::Chain { first, rest, span }
+                }
+                Expr::BinOp {
+                    lhs: first,
+                    op: first_op,
+                    rhs: second,
+                    ..
+                } if left_assoc(&op) && left_assoc(&first_op) => Expr::Chain {
+                    first,
+                    rest: vec![(first_op, *second), (op, rhs)],
+                    span,
+                },
+                lhs => Expr::BinOp {
+                    lhs: Box::new(lhs),
+                    op,
+                    rhs: Box::new(rhs),
+                    span,
+                },
+            })
+        })
+        .map_postfix(|lhs, op| {
+            let lhs = lhs?;
+            match op.as_rule() {
+                Rule::conversion => Ok(Expr::Convert {
+                    span: lhs.span().to(op.as_span().into()),
+                    unit: parse_unit(op.into_inner().last().unwrap())?,
+                    expr: Box::new(lhs),
+                }),
+                Rule::factorial => Ok(Expr::Factorial {
+                    span: lhs.span().to(op.as_span().into()),
+                    expr: Box::new(lhs),
+                }),
+                Rule::percent => Ok(Expr::Percent {
+                    span: lhs.span().to(op.as_span().into()),
+                    expr: Box::new(lhs),
+                }),
+                Rule::degree => Ok(Expr::Degrees {
+                    span: lhs.span().to(op.as_span().into()),
+                    expr: Box::new(lhs),
+                }),
+                _ => Err(unsupported(op.as_span())),
             }
         })
-        .map_prefix(|op, rhs| match op.as_rule() {
-            Rule::unary_minus => Expr::UnaryMinus(Box::new(rhs)),
-            _ => unreachable!(),
+        .map_prefix(|op, rhs| {
+            let rhs = rhs?;
+            match op.as_rule() {
+                Rule::unary_minus => Ok(Expr::UnaryMinus {
+                    span: Span::from(op.as_span()).to(rhs.span()),
+                    expr: Box::new(rhs),
+                }),
+                Rule::not => Ok(Expr::Not {
+                    span: Span::from(op.as_span()).to(rhs.span()),
+                    expr: Box::new(rhs),
+                }),
+                Rule::bit_not => Ok(Expr::BitNot {
+                    span: Span::from(op.as_span()).to(rhs.span()),
+                    expr: Box::new(rhs),
+                }),
+                _ => Err(unsupported(op.as_span())),
+            }
         })
         .parse(pairs)
 }
 
-pub fn repl() -> io::Result<()> {
-    const PROMPT: &str = ">> ";
-
-    loop {
-        let mut buffer = String::new();
-        print!("{}", PROMPT);
-        let _ = io::stdout().flush();
-        io::stdin().read_line(&mut buffer)?;
-        let line = buffer.trim();
-        match CalculatorParser::parse(Rule::equation, line) {
-            Ok(mut pairs) => {
-                let inner = parse_expr(pairs.next().unwrap().into_inner());
-                println!(
-                    "\nParsed: {:#?}",
-                    // inner of expr
-                    inner
-                );
-                println!("\n{}", inner.eval());
+/// Builds a `Unit` from a `unit_expr` like `km/h` or `kg*m^2`.
+fn parse_unit(pair: Pair<Rule>) -> Result<Unit, ParseError> {
+    let mut unit = Unit::default();
+    let mut divide = false;
+    for pair in pair.into_inner() {
+        if pair.as_rule() == Rule::unit_op {
+            divide = pair.as_str() == "/";
+            continue;
+        }
+        let mut inner = pair.clone().into_inner();
+        let name = inner.next().unwrap();
+        let named = Unit::named(name.as_str()).ok_or_else(|| {
+            ParseError::from_span(
+                ParseErrorKind::UnknownUnit {
+                    name: name.as_str().to_string(),
+                },
+                name.as_span(),
+            )
+        })?;
+        let exponent = match inner.next() {
+            Some(exponent) => exponent.as_str().parse::<i32>().ok(),
+            None => Some(1),
+        };
+        // Read left to right, so `m/s*A` is `m*A/s`
+        let sign = if divide { -1 } else { 1 };
+        unit = exponent
+            .and_then(|exponent| unit.mul(&named.pow(exponent.checked_mul(sign)?)?))
+            .ok_or_else(|| {
+                ParseError::from_span(
+                    ParseErrorKind::LiteralOutOfRange {
+                        literal: pair.as_str().to_string(),
+                    },
+                    pair.as_span(),
+                )
+            })?;
+    }
+    Ok(unit)
+}
+
+/// Reads a quantity whose unit isn't known, like `2 x^2/y`, as the product
+/// of the number and variables.
+fn implicit_product(coefficient: Expr, unit_expr: Pair<Rule>) -> Expr {
+    let mut op = Op::Multiply;
+    let mut product = coefficient;
+    for pair in unit_expr.into_inner() {
+        if pair.as_rule() == Rule::unit_op {
+            op = if pair.as_str() == "/" {
+                Op::Divide
+            } else {
+                Op::Multiply
+            };
+            continue;
+        }
+        let span = pair.as_span().into();
+        let mut inner = pair.into_inner();
+        let name = inner.next().unwrap();
+        let mut factor = Expr::Variable {
+            name: name.as_str().to_string(),
+            span: name.as_span().into(),
+        };
+        if let Some(exponent) = inner.next() {
+            factor = Expr::BinOp {
+                lhs: Box::new(factor),
+                op: Op::Power,
+                rhs: Box::new(Expr::Integer {
+                    value: exponent.as_str().parse().unwrap(),
+                    span: exponent.as_span().into(),
+                }),
+                span,
+            };
+        }
+        product = Expr::BinOp {
+            span: product.span().to(span),
+            lhs: Box::new(product),
+            op: op.clone(),
+            rhs: Box::new(factor),
+        };
+    }
+    product
+}
+
+/// Reports a grammar rule that `parse_expr` doesn't know how to build an
+/// `Expr` from, rather than panicking if the grammar and parser drift apart.
+fn unsupported(span: pest::Span) -> ParseError {
+    ParseError::from_span(
+        ParseErrorKind::UnsupportedSyntax {
+            text: span.as_str().to_string(),
+        },
+        span,
+    )
+}
+
+/// Which shorthand the parser accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Juxtaposition multiplies, as in `2x` or `2(3 + 4)`.
+    #[default]
+    Relaxed,
+    /// Every multiplication needs a `*`.
+    Strict,
+}
+
+impl Syntax {
+    pub const NAMES: &'static [&'static str] = &["relaxed", "strict"];
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Syntax, String> {
+        match s {
+            "relaxed" => Ok(Syntax::Relaxed),
+            "strict" => Ok(Syntax::Strict),
+            _ => Err(format!(
+                "unknown syntax '{}', expected one of: {}",
+                s,
+                Syntax::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Syntax::Relaxed => write!(f, "relaxed"),
+            Syntax::Strict => write!(f, "strict"),
+        }
+    }
+}
+
+/// How deeply brackets and conditionals may nest. The grammar recurses for
+/// each level, so deeper input is rejected before it's parsed.
+pub const MAX_NESTING: usize = 100;
+
+/// How deep the `Expr` built from a line may be. Evaluation recurses for
+/// each level, so this keeps very long or deep input from overflowing the
+/// stack.
+pub const MAX_DEPTH: usize = 256;
+
+/// Rejects input whose brackets or conditionals nest more than
+/// `MAX_NESTING` deep. Each `if` nests inside the one before it at the same
+/// bracket level, since an else branch extends as far right as it can.
+fn check_nesting(input: &str) -> Result<(), ParseError> {
+    // How many `if`s are open at each bracket level
+    let mut levels = vec![0];
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        match c {
+            '(' | '[' => levels.push(0),
+            ')' | ']' => {
+                if levels.len() > 1 {
+                    levels.pop();
+                }
+                continue;
             }
-            Err(e) => {
-                eprintln!("Parse failed: {:?}", e);
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                while let Some((index, _)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    end = index + 1;
+                }
+                if &input[start..end] != "if" {
+                    continue;
+                }
+                *levels.last_mut().unwrap() += 1;
             }
+            _ => continue,
+        }
+        let depth = levels.len() - 1 + levels.iter().sum::<usize>();
+        if depth > MAX_NESTING {
+            return Err(ParseError::new(
+                ParseErrorKind::NestingTooDeep { limit: MAX_NESTING },
+                Span::new(start, end),
+                input,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// How tightly an infix operator binds, matching `PRATT_PARSER`.
+fn precedence(rule: Rule) -> Option<u8> {
+    Some(match rule {
+        Rule::or => 1,
+        Rule::and => 2,
+        Rule::eq | Rule::ne | Rule::lt | Rule::le | Rule::gt | Rule::ge => 3,
+        Rule::range => 4,
+        Rule::bit_or => 5,
+        Rule::xor => 6,
+        Rule::bit_and => 7,
+        Rule::shift_left | Rule::shift_right => 8,
+        Rule::add | Rule::subtract => 9,
+        Rule::multiply | Rule::divide | Rule::modulo => 10,
+        Rule::implicit => 11,
+        Rule::power => 12,
+        _ => return None,
+    })
+}
+
+/// Returns a bound on the depth of the `Expr` built from `pair`, or an
+/// error at the first expression that would be more than `MAX_DEPTH` deep.
+/// Operators in a row at one precedence become a flat `Expr::Chain`, so
+/// only operands that nest count: the right side of a looser operator,
+/// powers, and prefix and postfix operators.
+fn check_depth(pair: &Pair<Rule>) -> Result<usize, ParseError> {
+    if pair.as_rule() != Rule::expr {
+        let mut nested = 0;
+        for child in pair.clone().into_inner() {
+            nested = nested.max(check_depth(&child)?);
+        }
+        return Ok(nested + 1);
+    }
+    // Follows the operators the way the Pratt parser does, keeping each
+    // one still waiting for its right operand with the depth of that operand
+    let mut waiting: Vec<(u8, usize)> = Vec::new();
+    let (mut base, mut prefixes, mut atom) = (0, 0, 0);
+    let (mut deepest, mut conversions) = (0, 0);
+    for child in pair.clone().into_inner() {
+        match child.as_rule() {
+            Rule::unary_minus | Rule::not | Rule::bit_not => prefixes += 1,
+            Rule::factorial | Rule::percent | Rule::degree => atom += 1,
+            Rule::conversion => conversions += 1,
+            rule => match precedence(rule) {
+                Some(precedence) => {
+                    deepest = deepest.max(base + prefixes + atom);
+                    let right = rule == Rule::power;
+                    while let Some(&(top, _)) = waiting.last() {
+                        if top < precedence || (top == precedence && right) {
+                            break;
+                        }
+                        waiting.pop();
+                    }
+                    let start = waiting.last().map_or(0, |&(_, depth)| depth);
+                    // Prefix operators apply to the whole power, as in -2^2
+                    base = start + 1 + if right { prefixes } else { 0 };
+                    waiting.push((precedence, base));
+                    (prefixes, atom) = (0, 0);
+                }
+                None => atom += check_depth(&child)?,
+            },
         }
     }
+    let depth = deepest.max(base + prefixes + atom) + conversions + 1;
+    if depth > MAX_DEPTH {
+        return Err(ParseError::from_span(
+            ParseErrorKind::ExpressionTooDeep { limit: MAX_DEPTH },
+            pair.as_span(),
+        ));
+    }
+    Ok(depth)
+}
+
+/// The leaves of the parse tree for a line of input, in order, with the
+/// text each one matched. For seeing how the grammar splits up a line.
+pub fn tokens(input: &str) -> Result<Vec<(Rule, &str)>, ParseError> {
+    check_nesting(input)?;
+    let pairs = CalculatorParser::parse(Rule::statement, input)
+        .map_err(|e| ParseError::from_pest(e, input))?;
+    Ok(pairs
+        .flatten()
+        .filter(|pair| pair.clone().into_inner().next().is_none())
+        .filter(|pair| pair.as_rule() != Rule::EOI)
+        .map(|pair| (pair.as_rule(), pair.as_str()))
+        .collect())
+}
+
+/// Parses a complete line of input into an `Expr`.
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    parse_with(input, Syntax::default())
+}
+
+/// Parses a line of input, accepting only the shorthand `syntax` allows.
+pub fn parse_with(input: &str, syntax: Syntax) -> Result<Expr, ParseError> {
+    check_nesting(input)?;
+    let mut pairs = CalculatorParser::parse(Rule::equation, input)
+        .map_err(|e| ParseError::from_pest(e, input))?;
+    let expr = pairs.next().unwrap();
+    check_depth(&expr)?;
+    parse_expr(expr.into_inner(), syntax)
+}
+
+/// Parses a complete line of input into a `Statement`.
+pub fn parse_statement(input: &str) -> Result<Statement, ParseError> {
+    parse_statement_with(input, Syntax::default())
+}
+
+/// Parses a line of input into a `Statement` using `syntax`.
+pub fn parse_statement_with(input: &str, syntax: Syntax) -> Result<Statement, ParseError> {
+    check_nesting(input)?;
+    let mut pairs = CalculatorParser::parse(Rule::statement, input)
+        .map_err(|e| ParseError::from_pest(e, input))?;
+    let pair = pairs.next().unwrap();
+    check_depth(&pair)?;
+    let statement = parse_statement_pair(pair, syntax)?;
+    let display = match pairs.next() {
+        Some(pair) if pair.as_rule() == Rule::display => pair,
+        _ => return Ok(statement),
+    };
+    let name = display.into_inner().last().unwrap();
+    match name.as_str().parse() {
+        Ok(format) => Ok(Statement::Display {
+            statement: Box::new(statement),
+            format,
+        }),
+        Err(_) => Err(ParseError::from_span(
+            ParseErrorKind::UnknownFormat {
+                name: name.as_str().to_string(),
+            },
+            name.as_span(),
+        )),
+    }
+}
+
+fn parse_statement_pair(pair: Pair<Rule>, syntax: Syntax) -> Result<Statement, ParseError> {
+    match pair.as_rule() {
+        Rule::assignment => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap().as_str().to_string();
+            let expr = parse_expr(inner.next().unwrap().into_inner(), syntax)?;
+            Ok(Statement::Assign { name, expr })
+        }
+        Rule::function_def => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap().as_str().to_string();
+            let mut params: Vec<String> = Vec::new();
+            let mut body = None;
+            for pair in inner {
+                match pair.as_rule() {
+                    Rule::ident if params.iter().any(|p| p == pair.as_str()) => {
+                        return Err(ParseError::from_span(
+                            ParseErrorKind::DuplicateParameter {
+                                name: pair.as_str().to_string(),
+                            },
+                            pair.as_span(),
+                        ));
+                    }
+                    Rule::ident => params.push(pair.as_str().to_string()),
+                    _ => body = Some(parse_expr(pair.into_inner(), syntax)?),
+                }
+            }
+            let body = body.unwrap();
+            Ok(Statement::Define {
+                name,
+                function: Function { params, body },
+            })
+        }
+        _ => Ok(Statement::Expr(parse_expr(pair.into_inner(), syntax)?)),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    type TestResult = Result<i32, Box<dyn std::error::Error>>;
+    use num_rational::BigRational;
+
+    type TestResult = Result<Value, Box<dyn std::error::Error>>;
+
+    fn ratio(numer: i64, denom: i64) -> Value {
+        Value::Rational(BigRational::new(numer.into(), denom.into()))
+    }
 
     fn test_expr_parse(input: &str) -> TestResult {
-        match CalculatorParser::parse(Rule::equation, input) {
-            Ok(mut pairs) => {
-                let inner = parse_expr(pairs.next().unwrap().into_inner());
-                Ok(inner.eval())
-            }
+        match parse(input) {
+            Ok(inner) => Ok(inner.eval(&Environment::new())?),
             Err(e) => {
-                eprintln!("Parse failed: {:?}", e);
+                eprintln!("{}", e.render(input));
                 unreachable!()
             }
         }
@@ -140,22 +1192,1051 @@ mod tests {
     #[test]
     fn run_tests() {
         let test_table = vec![
-            ("5 + 5", 10),
-            ("5 - 5", 0),
-            ("5 * 5", 25),
-            ("5 / 5", 1),
-            ("(13 * 25 / 2) - ((25 - 4) + (16 / 3) * 2)", 131),
-            ("5 * 6 * 7 + 24 - 16", 218),
-            ("750 / 5 + (6 * 2) / 2", 156),
-            ("1024 + 256 + 256 + 256 + 256 / (2)", 1920),
-            ("13 * 2 % 5", 1),
-            ("13 * (5 - 3)^2", 52),
-            ("2^3 + 3^2", 17),
-            ("16^2/3*2-1+5", 174),
+            ("5 + 5", Value::Integer(10)),
+            ("5 - 5", Value::Integer(0)),
+            ("5 * 5", Value::Integer(25)),
+            ("5 / 5", Value::Integer(1)),
+            ("(13 * 25 / 2) - ((25 - 4) + (16 / 3) * 2)", ratio(785, 6)),
+            ("5 * 6 * 7 + 24 - 16", Value::Integer(218)),
+            ("750 / 5 + (6 * 2) / 2", Value::Integer(156)),
+            ("1024 + 256 + 256 + 256 + 256 / (2)", Value::Integer(1920)),
+            ("13 * 2 % 5", Value::Integer(1)),
+            ("13 * (5 - 3)^2", Value::Integer(52)),
+            ("2^3 + 3^2", Value::Integer(17)),
+            ("16^2/3*2-1+5", ratio(524, 3)),
         ];
         for test in test_table.into_iter() {
             let (input, expected) = test;
-            assert_eq!(test_expr_parse(input).unwrap(), expected);
+            assert_eq!(test_expr_parse(input).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn precedence_tests() {
+        let test_table = vec![
+            ("2^3^2", Value::Integer(512)),
+            ("(2^3)^2", Value::Integer(64)),
+            ("-2^2", Value::Integer(-4)),
+            ("(-2)^2", Value::Integer(4)),
+            ("-2^-2", ratio(-1, 4)),
+            ("2^-2^2", ratio(1, 16)),
+            ("2^-1 * 4", Value::Integer(2)),
+            ("-3 * 2", Value::Integer(-6)),
+            ("2 * -3", Value::Integer(-6)),
+            ("-2 - -2", Value::Integer(0)),
+            ("10 - 2 - 3", Value::Integer(5)),
+            ("100 / 10 / 5", Value::Integer(2)),
+            ("2 * 3^2", Value::Integer(18)),
+            ("3^2 * 2", Value::Integer(18)),
+            ("17 % 5 * 2", Value::Integer(4)),
+            ("1 + 2 * 3 - 4 / 2", Value::Integer(5)),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(test_expr_parse(input).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn booleans_and_conditionals() {
+        let test_table = vec![
+            ("1 < 2", Value::Bool(true)),
+            ("2 <= 2 && 3 >= 4", Value::Bool(false)),
+            ("1/3 == 2/6", Value::Bool(true)),
+            ("0.5 != 1/2", Value::Bool(false)),
+            ("2^64 > 2^63", Value::Bool(true)),
+            ("true == !false", Value::Bool(true)),
+            ("!(1 > 2) || false", Value::Bool(true)),
+            // && binds tighter than ||
+            ("true || false && false", Value::Bool(true)),
+            // Comparisons bind looser than arithmetic
+            ("1 + 1 == 2 * 1", Value::Bool(true)),
+            ("if 3 > 2 then 10 else 20", Value::Integer(10)),
+            (
+                "if false then 1 else if true then 2 else 3",
+                Value::Integer(2),
+            ),
+            ("1 + (if 1 == 1 then 2 else 3) * 2", Value::Integer(5)),
+            // The else branch extends to the end
+            ("if false then 1 else 2 + 3", Value::Integer(5)),
+            // Short-circuiting skips the erroring side
+            ("false && 1 / 0 == 1", Value::Bool(false)),
+            ("true || undefined_name", Value::Bool(true)),
+            ("if true then 1 else 1 / 0", Value::Integer(1)),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(test_expr_parse(input).unwrap(), expected, "{}", input);
+        }
+
+        let mut env = Environment::new();
+        for line in [
+            "fact(n) = if n <= 1 then 1 else n * fact(n - 1)",
+            "iffy = 2",
+            "count(n) = if n <= 0 then 0 else 1 + count(n - 1)",
+        ] {
+            parse_statement(line).unwrap().execute(&mut env).unwrap();
+        }
+        let result = parse("fact(25) / fact(23) + iffy").unwrap().eval(&env);
+        assert_eq!(result.unwrap(), Value::Integer(602));
+        // count(n) nests n + 1 calls, and recursion stops at the call depth
+        // limit rather than overflowing the native stack
+        let depth = MAX_CALL_DEPTH as i64 - 1;
+        let result = parse(&format!("count({})", depth)).unwrap().eval(&env);
+        assert_eq!(result.unwrap(), Value::Integer(depth));
+        let result = parse(&format!("count({})", depth + 1)).unwrap().eval(&env);
+        assert!(matches!(result, Err(EvalError::RecursionLimit { .. })));
+    }
+
+    #[test]
+    fn programmer_mode() {
+        let fixed = |value: i128, ty: IntType| Value::Fixed(value, ty);
+        let test_table = vec![
+            ("0xff", Value::Integer(255)),
+            ("0XfF + 0b101 + 0o17", Value::Integer(275)),
+            ("0x10000000000000000", Value::BigInt(BigInt::from(1) << 64)),
+            ("0b1100 & 0b1010", Value::Integer(0b1000)),
+            ("0b1100 | 0b1010", Value::Integer(0b1110)),
+            ("0b1100 xor 0b1010", Value::Integer(0b0110)),
+            ("~0", Value::Integer(-1)),
+            ("-8 >> 1", Value::Integer(-4)),
+            ("-1 >> 1000", Value::Integer(-1)),
+            ("1 << 64", Value::BigInt(BigInt::from(1) << 64)),
+            ("-6 & 0xff", Value::Integer(250)),
+            // From tightest: shifts, then &, then xor, then |
+            ("1 | 6 xor 7 & 5 << 1", Value::Integer(5)),
+            ("1 + 1 << 2", Value::Integer(8)),
+            ("0x0f & 0xf0 == 0", Value::Bool(true)),
+            ("u8(-1)", fixed(255, IntType::U8)),
+            ("i8(200)", fixed(-56, IntType::I8)),
+            ("i16(u8(255))", fixed(255, IntType::I16)),
+            ("u8(200) + 50", fixed(250, IntType::U8)),
+            ("7 / u8(2)", fixed(3, IntType::U8)),
+            ("i8(-7) % 2", fixed(-1, IntType::I8)),
+            ("~u8(0b1111)", fixed(0xf0, IntType::U8)),
+            ("~i8(0)", fixed(-1, IntType::I8)),
+            ("u8(1) << 7", fixed(128, IntType::U8)),
+            ("i8(-128) >> 9", fixed(-1, IntType::I8)),
+            ("u32(2) ^ 31", fixed(1 << 31, IntType::U32)),
+            ("u8(6) ^ u64(2)", fixed(36, IntType::U8)),
+            ("u64(2^64 - 1)", fixed(u64::MAX.into(), IntType::U64)),
+            ("u8(10) * 0.5", Value::Real(5.0)),
+            ("u8(3) / (1/2)", Value::Integer(6)),
+            ("u8(200) > 100", Value::Bool(true)),
+            ("gcd(u8(12), 18)", Value::Integer(6)),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(test_expr_parse(input).unwrap(), expected, "{}", input);
+        }
+
+        let mut env = Environment::new();
+        env.set_overflow_mode(OverflowMode::Wrapping);
+        let test_table = vec![
+            ("u8(200) + 100", fixed(44, IntType::U8)),
+            ("u8(0) - 1", fixed(255, IntType::U8)),
+            ("-i8(-128)", fixed(-128, IntType::I8)),
+            ("i8(-128) / -1", fixed(-128, IntType::I8)),
+            ("u8(1) + 511", fixed(0, IntType::U8)),
+            ("u8(0xff) << 4", fixed(0xf0, IntType::U8)),
+            ("u8(1) << 1000", fixed(0, IntType::U8)),
+            ("u8(3) ^ 300", fixed(113, IntType::U8)),
+            ("i64(2) ^ (2^100)", fixed(0, IntType::I64)),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            let expr = parse(input).unwrap();
+            assert_eq!(expr.eval(&env).unwrap(), expected, "{}", input);
+        }
+        assert_eq!(fixed(-56, IntType::I8).to_string(), "-56");
+    }
+
+    #[test]
+    fn units() {
+        let quantity = |input: &str| test_expr_parse(input).unwrap().to_string();
+        let test_table = vec![
+            ("3 m / 2 s", "1.5 m/s"),
+            ("60 mph to km/h", "96.56064 km/h"),
+            ("1 km + 500 m", "1.5 km"),
+            ("500 m + 1 km", "1500 m"),
+            ("9.8 m/s^2 * 70 kg to N", "686 N"),
+            ("2 m * 3", "6 m"),
+            ("(3 m)^2", "9 m^2"),
+            ("(2 s)^-1", "0.5 1/s"),
+            ("6 m^2 / 2 m", "3 m"),
+            ("-2 m", "-2 m"),
+            ("1 inch to cm", "2.54 cm"),
+            ("1 kWh to J", "3600000 J"),
+            ("2 m * 1 / 4 s to km/h", "1.8 km/h"),
+            ("1 km / 1 m", "1000"),
+            ("3 m/m", "3"),
+            ("3 m > 2 ft", "true"),
+            ("1 ft == 12 inch", "true"),
+            ("7 m % 2 m", "1 m"),
+            ("-0.0 m == 0 m", "true"),
+            ("0 m * -1 < 0 m", "false"),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(quantity(input), expected, "{}", input);
         }
+
+        let mut env = Environment::new();
+        for line in ["d = 100 km", "t = 2 h", "v = d / t"] {
+            parse_statement(line).unwrap().execute(&mut env).unwrap();
+        }
+        let result = parse("v to m/s").unwrap().eval(&env).unwrap();
+        assert_eq!(
+            result.format(NumberFormat::Engineering),
+            "13.8888888888889e0 m/s"
+        );
+
+        let err = parse("1 m to furlong").unwrap_err();
+        assert_eq!(err.to_string(), "unknown unit 'furlong'");
+        assert_eq!(err.span, Span::new(7, 14));
+        // Without implicit multiplication, an unknown name after a number
+        // can only be a unit
+        let err = parse_with("5 furlong", Syntax::Strict).unwrap_err();
+        assert_eq!(err.to_string(), "unknown unit 'furlong'");
+        assert_eq!(err.span, Span::new(2, 9));
+    }
+
+    #[test]
+    fn matrices() {
+        let matrix = |input: &str| test_expr_parse(input).unwrap().to_string();
+        let test_table = vec![
+            ("[1, 2; 3, 4]", "[1, 2; 3, 4]"),
+            ("[1; 2; 3]", "[1; 2; 3]"),
+            ("[]", "[]"),
+            ("[1, 2] + [3, 4]", "[4, 6]"),
+            ("[1, 2; 3, 4] * 2", "[2, 4; 6, 8]"),
+            ("1 - [1, 2]", "[0, -1]"),
+            ("[2, 4] / 4", "[1/2, 1]"),
+            ("-[1, -2]", "[-1, 2]"),
+            ("[7, 8] % 3", "[1, 2]"),
+            ("[1, 2; 3, 4] * [5; 6]", "[17; 39]"),
+            ("[1, 2] * [3; 4]", "[11]"),
+            ("[1, 2; 3, 4] ^ 2", "[7, 10; 15, 22]"),
+            ("[1, 1; 1, 0] ^ 10", "[89, 55; 55, 34]"),
+            ("[2, 0; 0, 4] ^ -1", "[1/2, 0; 0, 1/4]"),
+            ("[1, 2; 3, 4] ^ 0", "[1, 0; 0, 1]"),
+            ("[1, 2; 3, 4] / [1, 2; 3, 4]", "[1, 0; 0, 1]"),
+            ("1 / [2, 0; 0, 4]", "[1/2, 0; 0, 1/4]"),
+            ("[1, 2] == [1.0, 2]", "true"),
+            ("[1, 2] != [1; 2]", "true"),
+            ("det([1, 2; 3, 4])", "-2"),
+            ("det([2, 0, 1; 1, 3, 2; 1, 1, 2])", "6"),
+            ("det([1, 2; 2, 4])", "0"),
+            ("inv([1, 2; 3, 4])", "[-2, 1; 3/2, -1/2]"),
+            ("transpose([1, 2, 3; 4, 5, 6])", "[1, 4; 2, 5; 3, 6]"),
+            ("dot([1, 2, 3], [4; 5; 6])", "32"),
+            ("cross([1, 0, 0], [0, 1, 0])", "[0, 0, 1]"),
+            ("solve([2, 1; 1, 3], [3; 5])", "[4/5; 7/5]"),
+            ("solve([2, 1; 1, 3], [3, 5])", "[4/5, 7/5]"),
+            ("[1, 2.5] * 2", "[2, 5]"),
+            ("[1, 1i]", "[1, 1i]"),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(matrix(input), expected, "{}", input);
+        }
+
+        let err = parse("[1, 2; 3]").unwrap_err();
+        assert_eq!(err.to_string(), "matrix rows have different lengths");
+        assert_eq!(err.span, Span::new(0, 9));
+    }
+
+    #[test]
+    fn constants() {
+        let test_table = vec![
+            ("pi", "3.141592653589793"),
+            ("tau / 2 == pi", "true"),
+            ("ln(e)", "1"),
+            ("phi^2 - phi", "1"),
+            ("2pi", "6.283185307179586"),
+            ("cos(pi)", "-1"),
+            ("c", "299792458 m/s"),
+            ("c * 2 s", "599584916 m"),
+            ("c * 1 yr to km", "9460730472580.8 km"),
+            (
+                "G * 5.972e24 kg / (6371 km)^2 to m/s^2",
+                "9.81997342622468 m/s^2",
+            ),
+            ("h", "6.62607015e-34 J*s"),
+            ("2 h", "2 h"),
+            ("k_B * 300 K to eV", "0.0258519997864355 eV"),
+            ("N_A * 2 mol", "1.204428152e24"),
+        ];
+        let env = Environment::new();
+        for (input, expected) in test_table.into_iter() {
+            let result = parse(input).map_err(|e| e.to_string()).and_then(|expr| {
+                expr.eval(&env)
+                    .map(|value| value.to_string())
+                    .map_err(|e| e.to_string())
+            });
+            assert_eq!(result.unwrap_or_else(|e| e), expected, "{}", input);
+        }
+
+        // Variables hide constants of the same name
+        let mut env = Environment::new();
+        parse_statement("e = 5").unwrap().execute(&mut env).unwrap();
+        assert_eq!(
+            parse("e + 1").unwrap().eval(&env).unwrap(),
+            Value::Integer(6)
+        );
+        let mut env = Environment::new();
+        parse_statement("f(pi) = pi + 1")
+            .unwrap()
+            .execute(&mut env)
+            .unwrap();
+        assert_eq!(
+            parse("f(2)").unwrap().eval(&env).unwrap(),
+            Value::Integer(3)
+        );
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        let mut env = Environment::new();
+        for line in ["x = 3", "y = 2", "f(t) = t + 1"] {
+            parse_statement(line).unwrap().execute(&mut env).unwrap();
+        }
+        let eval = |input: &str| parse(input).unwrap().eval(&env).unwrap().to_string();
+        let test_table = vec![
+            ("2(3 + 4)", "14"),
+            ("(1 + 2)(3 + 4)", "21"),
+            ("2x", "6"),
+            ("2 x", "6"),
+            ("x y", "6"),
+            ("2x y", "12"),
+            ("3 [1, 2]", "[3, 6]"),
+            ("2 f(1)", "4"),
+            ("2 sqrt(16)", "8"),
+            // `^` binds tighter than juxtaposition, however it's spaced
+            ("2x^2", "18"),
+            ("2x ^ 2", "18"),
+            ("2 x ^ 2", "18"),
+            ("2x^y", "18"),
+            ("2x ^ (1 + 1)", "18"),
+            ("2(3)^2", "18"),
+            ("y x^2", "18"),
+            ("x^2 y", "18"),
+            ("x^2y", "18"),
+            ("x ^ 2 y", "18"),
+            ("2^3 m", "8 m"),
+            ("3 m ^ 2", "3 m^2"),
+            ("2x^2.5", "31.176914536239792"),
+            // Juxtaposition binds tighter than `*` and `/`
+            ("1/2x", "1/6"),
+            ("1/2(3)", "1/6"),
+            ("12 / 2(3)", "2"),
+            // Prefix minus applies to the term before it's multiplied
+            ("-2x", "-6"),
+            ("-(1)(2)", "-2"),
+            ("2x/y", "3"),
+            // A name before parentheses is always a call, and a known unit
+            // name is a unit unless a variable has taken it
+            ("f(2)", "3"),
+            ("2 m", "2 m"),
+            ("2 km/h", "2 km/h"),
+            ("x m", "3 m"),
+            // Keywords end the term
+            ("if 2x > 5 then 1 else 0", "1"),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(eval(input), expected, "{}", input);
+        }
+
+        // Once a variable takes a unit's name, a number before it multiplies
+        for line in ["m = 5", "area(s) = 3 s^2"] {
+            parse_statement(line).unwrap().execute(&mut env).unwrap();
+        }
+        let eval = |input: &str| parse(input).unwrap().eval(&env).unwrap().to_string();
+        for (input, expected) in [
+            ("2m", "10"),
+            ("2 m ^ 2", "50"),
+            ("2 km", "2 km"),
+            ("2 m/s", "10 1/s"),
+            ("area(2)", "12"),
+            ("3 s^2", "3 s^2"),
+        ] {
+            assert_eq!(eval(input), expected, "{}", input);
+        }
+        let strict = parse_with("2 m", Syntax::Strict).unwrap();
+        assert_eq!(strict.eval(&env).unwrap().to_string(), "2 m");
+
+        for input in ["2(3)", "2x", "(1)(2)", "x y"] {
+            assert!(parse_with(input, Syntax::Strict).is_err(), "{}", input);
+        }
+        let err = parse_with("(1)(2)", Syntax::Strict).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "implicit multiplication isn't allowed in strict syntax"
+        );
+        let err = parse("2 3").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected end of input or an operator, found '3'"
+        );
+    }
+
+    #[test]
+    fn postfix_operators() {
+        let mut env = Environment::new();
+        env.push_result(Value::Integer(5));
+        let eval = |input: &str| parse(input).unwrap().eval(&env).unwrap().to_string();
+        let test_table = vec![
+            ("5!", "120"),
+            ("0!", "1"),
+            ("3!!", "720"),
+            ("5! / 3!", "20"),
+            ("u8(5)!", "120"),
+            // Postfix operators bind tighter than prefix minus and `^`
+            ("-3!", "-6"),
+            ("2^3!", "64"),
+            // `!` directly before `=` is the `!=` operator
+            ("5 != 3", "true"),
+            ("5!=3", "true"),
+            ("3! == 6", "true"),
+            // `%` with nothing after it that could be an operand is a
+            // percentage, otherwise it's modulo
+            ("50%", "1/2"),
+            ("(50)%", "1/2"),
+            ("200 * 15%", "30"),
+            ("50% * 4", "2"),
+            ("12.5%", "0.125"),
+            ("7 % 3", "1"),
+            ("7 %3", "1"),
+            ("10 % (-3)", "1"),
+            ("10 % -3", "1"),
+            ("7%-3", "1"),
+            ("10 % $1", "0"),
+            ("50% - 10", "0"),
+            ("(50%) - 10", "-19/2"),
+            ("200 * (15%) - 1", "29"),
+            ("50% + 10", "21/2"),
+            ("50% != 1", "true"),
+            ("50% == 1/2", "true"),
+            ("10 % (4)", "2"),
+            ("[10, 20]%", "[1/10, 1/5]"),
+            // Degrees convert to radians
+            ("180°", "3.141592653589793"),
+            ("sin(90°)", "1"),
+            ("nCr(5, 2)", "10"),
+            ("nCr(50, 25)", "126410606437752"),
+            ("nCr(5, 7)", "0"),
+            ("nPr(5, 2)", "20"),
+            ("nPr(5, 0)", "1"),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(eval(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn lists() {
+        let list = |input: &str| test_expr_parse(input).unwrap().to_string();
+        let test_table = vec![
+            ("1..5", "[1, 2, 3, 4, 5]"),
+            ("3..1", "[3, 2, 1]"),
+            ("1..1 + 2", "[1, 2, 3]"),
+            ("1..3 == [1, 2, 3]", "true"),
+            ("sum(1..100)", "5050"),
+            ("sum(1, 2, 3)", "6"),
+            ("sum([1, 2], 3)", "6"),
+            ("sum([])", "0"),
+            ("product(1..10)", "3628800"),
+            ("count([4, 8, 15, 16, 23, 42])", "6"),
+            ("mean([4, 8, 15, 16, 23, 42])", "18"),
+            ("mean(1, 2)", "3/2"),
+            ("mean([1.5, 2.5])", "2"),
+            ("median([3, 1, 2])", "2"),
+            ("median(1..4)", "5/2"),
+            ("mode([1, 3, 2, 2, 3])", "3"),
+            ("mode([2, 1.0, 1, 2])", "2"),
+            ("mode(1..20000)", "1"),
+            ("variance([2, 4, 4, 4, 5, 5, 7, 9])", "32/7"),
+            ("stdev([1, 3, 5])", "2"),
+            ("percentile(1..5, 50)", "3"),
+            ("percentile([4, 1, 3, 2], 25)", "7/4"),
+            ("percentile([10, 20], 100)", "20"),
+            ("min(5..10)", "5"),
+            ("max([3, 9], 4)", "9"),
+            ("max([1; 7; 2])", "7"),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(list(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn complex_numbers() {
+        let complex = |input: &str| test_expr_parse(input).unwrap().to_string();
+        let test_table = vec![
+            ("3 + 2i", "3 + 2i"),
+            ("3 - 2.5i", "3 - 2.5i"),
+            ("sqrt(-1)", "1i"),
+            ("sqrt(-4) + 1", "1 + 2i"),
+            ("(-4) ^ 0.5", "2i"),
+            ("1i * 1i", "-1"),
+            ("(1i) ^ 2", "-1"),
+            ("(1 + 1i) ^ 3", "-2 + 2i"),
+            ("(1 + 2i) * (3 - 1i)", "5 + 5i"),
+            ("(1 + 2i) / (1 - 1i)", "-0.5 + 1.5i"),
+            ("-(2 - 3i)", "-2 + 3i"),
+            ("(3 + 4i) - 4i", "3"),
+            ("1i ^ -1", "-1i"),
+            ("2 ^ 1i", "0.7692389013639721 + 0.6389612763136348i"),
+            ("sqrt(2i)", "1 + 1i"),
+            ("re(3 - 4i)", "3"),
+            ("im(3 - 4i)", "-4"),
+            ("im(1/2)", "0"),
+            ("re(1/2)", "1/2"),
+            ("conj(3 - 4i)", "3 + 4i"),
+            ("abs(3 - 4i)", "5"),
+            ("arg(1i) * 2", "3.141592653589793"),
+            ("arg(-1)", "3.141592653589793"),
+            ("3 + 2i == 2i + 3", "true"),
+            ("1i != 1", "true"),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(complex(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn real_numbers() {
+        let test_table = vec![
+            ("7 / 2.0", Value::Real(3.5)),
+            ("1.5 + 1", Value::Real(2.5)),
+            (".5 * 4", Value::Real(2.0)),
+            ("1e-3", Value::Real(0.001)),
+            ("2.5E2 / 5", Value::Real(50.0)),
+            ("2^-1.0", Value::Real(0.5)),
+            ("4 ^ 0.5", Value::Real(2.0)),
+            ("-1.5 % 1", Value::Real(-0.5)),
+            ("6 / 3", Value::Integer(2)),
+            // Signed zeros compare equal
+            ("-0.0 == 0", Value::Bool(true)),
+            ("0.0 * -1 == 0", Value::Bool(true)),
+            ("-0.0 < 0", Value::Bool(false)),
+            ("-0.0 <= 0.0", Value::Bool(true)),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(test_expr_parse(input).unwrap(), expected, "{}", input);
+        }
+        assert_eq!(Value::Real(3.5).to_string(), "3.5");
+        assert_eq!(Value::Real(1e-12).to_string(), "1e-12");
+    }
+
+    #[test]
+    fn rationals() {
+        let test_table = vec![
+            ("7 / 2", ratio(7, 2)),
+            ("1/3 + 1/6", ratio(1, 2)),
+            ("1/3 - 1/3", Value::Integer(0)),
+            ("(2/3) * (3/4)", ratio(1, 2)),
+            ("(1/2) / (1/4)", Value::Integer(2)),
+            ("(7/2) % 1", ratio(1, 2)),
+            ("(2/3)^3", ratio(8, 27)),
+            ("(2/3)^-2", ratio(9, 4)),
+            ("2^-1", ratio(1, 2)),
+            ("-(1/3)", ratio(-1, 3)),
+            ("1/2 + 0.25", Value::Real(0.75)),
+            ("(1/4)^0.5", Value::Real(0.5)),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(test_expr_parse(input).unwrap(), expected, "{}", input);
+        }
+        assert_eq!(ratio(-5, 3).to_string(), "-5/3");
+        assert_eq!(ratio(1, 4).format(NumberFormat::Decimal), "0.25");
+        assert_eq!(ratio(1, 4).format(NumberFormat::Fraction), "1/4");
+    }
+
+    #[test]
+    fn assignments() {
+        let mut env = Environment::new();
+        let test_table = vec![
+            ("x = 3 * 4", Value::Integer(12)),
+            ("x", Value::Integer(12)),
+            ("rate_2 = x / 8", ratio(3, 2)),
+            ("x * rate_2 - 1", Value::Integer(17)),
+            ("x = -x", Value::Integer(-12)),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            let statement = parse_statement(input).unwrap();
+            assert_eq!(
+                statement.execute(&mut env).unwrap(),
+                Some(expected),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(env.get("x"), Some(&Value::Integer(-12)));
+
+        let err = parse_statement("y = x + z")
+            .unwrap()
+            .execute(&mut env)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            EvalError::UndefinedVariable {
+                name: "z".to_string(),
+                span: Span::new(8, 9)
+            }
+        );
+        assert_eq!(env.get("y"), None);
+    }
+
+    #[test]
+    fn builtin_functions() {
+        let test_table = vec![
+            ("sqrt(16)", Value::Integer(4)),
+            ("sqrt(9/4)", ratio(3, 2)),
+            ("sqrt(2)", Value::Real(2f64.sqrt())),
+            ("abs(-7/2) + abs(-1)", ratio(9, 2)),
+            ("min(3, 1/2, 2.5)", ratio(1, 2)),
+            ("max(3, 1/2, 2.5)", Value::Integer(3)),
+            ("floor(-7/2)", Value::Integer(-4)),
+            ("ceil(2.1)", Value::Integer(3)),
+            ("round(5/2)", Value::Integer(3)),
+            ("sin(0) + cos(0)", Value::Real(1.0)),
+            ("tan(0)", Value::Real(0.0)),
+            ("ln(exp(2))", Value::Real(2.0)),
+            ("log(1000)", Value::Real(3.0)),
+            ("log(8, 2)", Value::Real(3.0)),
+            ("gcd(12, 18, 8)", Value::Integer(2)),
+            ("lcm(4, 6)", Value::Integer(12)),
+            ("max(1, sqrt(4) * 3) - 1", Value::Integer(5)),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(test_expr_parse(input).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn user_functions() {
+        let mut env = Environment::new();
+        for line in [
+            "f(x, y) = x^2 + y",
+            "y = 100",
+            "g(y) = f(y, 1) + y",
+            "h() = y",
+        ] {
+            parse_statement(line).unwrap().execute(&mut env).unwrap();
+        }
+        let test_table = vec![
+            ("f(3, 4)", Value::Integer(13)),
+            // Parameters shadow globals, and don't leak into callees
+            ("g(2)", Value::Integer(7)),
+            ("h()", Value::Integer(100)),
+            ("f(1/2, 0) * 4", Value::Integer(1)),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            let expr = parse(input).unwrap();
+            assert_eq!(expr.eval(&env).unwrap(), expected, "{}", input);
+        }
+
+        parse_statement("loop(n) = loop(n + 1)")
+            .unwrap()
+            .execute(&mut env)
+            .unwrap();
+        parse_statement("bad(x) = x / 0")
+            .unwrap()
+            .execute(&mut env)
+            .unwrap();
+        // (input, message, span start, span end)
+        let test_table = vec![
+            ("f(1)", "f takes 2 arguments but was given 1", 0, 4),
+            ("1 + loop(0)", "maximum call depth exceeded in loop", 4, 11),
+            ("2 * bad(1)", "division by zero", 4, 10),
+        ];
+        for (input, message, start, end) in test_table.into_iter() {
+            let err = parse(input).unwrap().eval(&env).unwrap_err();
+            assert_eq!(err.to_string(), message, "{}", input);
+            assert_eq!(err.span(), Span::new(start, end), "{}", input);
+        }
+
+        let err = parse_statement("f(x, x) = x").unwrap_err();
+        assert_eq!(err.to_string(), "parameter 'x' is declared more than once");
+    }
+
+    #[test]
+    fn eval_errors() {
+        // (input, message, span start, span end)
+        let test_table = vec![
+            ("1 / 0", "division by zero", 0, 5),
+            ("7 % (3 - 3)", "division by zero", 0, 10),
+            ("1 + 2^(2^40)", "arithmetic overflow", 4, 11),
+            ("10^100000000", "arithmetic overflow", 0, 12),
+            ("0^-1", "division by zero", 0, 4),
+            ("(1/2) / (1/2 - 1/2)", "division by zero", 1, 18),
+            ("1.5 / 0", "division by zero", 0, 7),
+            ("ln(-1)", "result is not a real number", 0, 6),
+            ("1e300 * 1e300", "arithmetic overflow", 0, 13),
+            (
+                "1i < 2",
+                "expected a real number, found a complex number",
+                0,
+                6,
+            ),
+            (
+                "5 % 2i",
+                "expected a real number, found a complex number",
+                0,
+                6,
+            ),
+            (
+                "sin(1i)",
+                "expected a real number, found a complex number",
+                0,
+                7,
+            ),
+            (
+                "1i & 1",
+                "expected an integer, found a complex number",
+                0,
+                6,
+            ),
+            ("2i / 0", "division by zero", 0, 6),
+            (
+                "(0 - 1)!",
+                "invalid argument to factorial: expected a non-negative integer",
+                1,
+                8,
+            ),
+            ("2.5!", "expected an integer, found a real number", 0, 4),
+            ("100000!", "arithmetic overflow", 0, 7),
+            ("u8(6)!", "arithmetic overflow", 0, 6),
+            (
+                "nCr(-1, 2)",
+                "invalid argument to nCr: expected non-negative integers",
+                0,
+                10,
+            ),
+            ("nPr(10^9, 10^9)", "arithmetic overflow", 0, 15),
+            ("1..2.5", "expected an integer, found a real number", 0, 6),
+            ("1..10^7", "arithmetic overflow", 0, 7),
+            ("product(1..1000000)", "arithmetic overflow", 0, 19),
+            (
+                "mean([])",
+                "invalid argument to mean: expected at least 1 value",
+                0,
+                8,
+            ),
+            (
+                "max([])",
+                "invalid argument to max: expected at least 1 value",
+                0,
+                7,
+            ),
+            (
+                "variance(1)",
+                "invalid argument to variance: expected at least 2 values",
+                0,
+                11,
+            ),
+            (
+                "percentile(1..5, 101)",
+                "invalid argument to percentile: percentile must be between 0 and 100",
+                0,
+                21,
+            ),
+            (
+                "sum([1, 1i])",
+                "expected a real number, found a complex number",
+                0,
+                12,
+            ),
+            (
+                "sum([1, 2] == 1)",
+                "expected a matrix, found a number",
+                4,
+                15,
+            ),
+            (
+                "[1, 2] + [1, 2, 3]",
+                "incompatible shapes: 1x2 and 1x3",
+                0,
+                18,
+            ),
+            ("[1, 2] * [3, 4]", "incompatible shapes: 1x2 and 1x2", 0, 15),
+            ("det([1, 2])", "expected a square matrix, found 1x2", 0, 11),
+            (
+                "transpose(1..100000) * (1..100000)",
+                "arithmetic overflow",
+                0,
+                33,
+            ),
+            ("[2] ^ (2^24)", "arithmetic overflow", 0, 11),
+            ("[2] ^ (2^40)", "arithmetic overflow", 0, 11),
+            (
+                "[1, 2; 3, 4] ^ 0.5",
+                "expected an integer, found a real number",
+                0,
+                18,
+            ),
+            ("inv([1, 2; 2, 4])", "matrix is singular", 0, 17),
+            (
+                "[1, 2] < [3, 4]",
+                "expected a number, found a matrix",
+                0,
+                15,
+            ),
+            ("[1, true]", "expected a number, found a boolean", 4, 8),
+            ("[[1]]", "expected a number, found a matrix", 1, 4),
+            ("sqrt([4])", "expected a number, found a matrix", 0, 9),
+            ("det(4)", "expected a matrix, found a number", 0, 6),
+            (
+                "cross([1, 2], [3, 4])",
+                "invalid argument to cross: expected vectors of length 3",
+                0,
+                21,
+            ),
+            (
+                "dot([1, 2; 3, 4], [1, 2])",
+                "invalid argument to dot: expected vectors, found a 2x2 matrix",
+                0,
+                25,
+            ),
+            (
+                "solve([1, 0; 0, 1], [1, 2, 3])",
+                "incompatible shapes: 2x2 and 3x1",
+                0,
+                30,
+            ),
+            (
+                "2i * 1 m",
+                "expected a real number, found a complex number",
+                0,
+                8,
+            ),
+            ("ln(0)", "result is not a real number", 0, 5),
+            ("frob(1)", "unknown function 'frob'", 0, 7),
+            ("sqrt(1, 2)", "sqrt takes 1 argument but was given 2", 0, 10),
+            ("log()", "log takes 1 to 2 arguments but was given 0", 0, 5),
+            (
+                "gcd(4)",
+                "gcd takes at least 2 arguments but was given 1",
+                0,
+                6,
+            ),
+            (
+                "gcd(4, 1.5)",
+                "invalid argument to gcd: expected integers",
+                0,
+                11,
+            ),
+            ("1 + true", "expected a number, found a boolean", 0, 8),
+            ("-true", "expected a number, found a boolean", 0, 5),
+            ("!1", "expected a boolean, found a number", 1, 2),
+            ("1 && true", "expected a boolean, found a number", 0, 1),
+            (
+                "if 1 then 2 else 3",
+                "expected a boolean, found a number",
+                3,
+                4,
+            ),
+            ("true < false", "expected a number, found a boolean", 0, 12),
+            ("sqrt(true)", "expected a number, found a boolean", 0, 10),
+            ("1.5 & 1", "expected an integer, found a real number", 0, 7),
+            ("1/2 | 1", "expected an integer, found a fraction", 0, 7),
+            ("1 << -1", "negative shift amount", 0, 7),
+            ("1 << 2^40", "arithmetic overflow", 0, 9),
+            ("u8(200) + 56", "arithmetic overflow", 0, 12),
+            ("u8(1) + 256", "arithmetic overflow", 0, 11),
+            ("-u8(1)", "arithmetic overflow", 0, 6),
+            ("u8(128) << 1", "arithmetic overflow", 0, 12),
+            ("i8(2) ^ 7", "arithmetic overflow", 0, 9),
+            ("u8(2) ^ -1", "negative exponent in integer power", 0, 10),
+            ("u8(1) / 0", "division by zero", 0, 9),
+            ("u8(1) + i8(1)", "expected u8, found i8", 0, 13),
+            ("u8(1.5)", "invalid argument to u8: expected integers", 0, 7),
+            ("1 m + 1 s", "incompatible units: m and s", 0, 9),
+            ("1 m + 1", "incompatible units: m and a plain number", 0, 7),
+            ("60 mph to kg", "incompatible units: mph and kg", 0, 12),
+            ("sqrt(4 m)", "expected a number, found a quantity", 0, 9),
+            ("2 ^ (1 m)", "expected an integer, found a quantity", 0, 8),
+            ("1 m / 0", "division by zero", 0, 7),
+        ];
+        for (input, message, start, end) in test_table.into_iter() {
+            let err = test_expr_parse(input).unwrap_err();
+            let err = err.downcast_ref::<EvalError>().unwrap();
+            assert_eq!(err.to_string(), message, "{}", input);
+            assert_eq!(err.span(), Span::new(start, end), "{}", input);
+        }
+    }
+
+    #[test]
+    fn big_integers() {
+        let two = BigInt::from(2);
+        let test_table = vec![
+            ("2^31", Value::Integer(1 << 31)),
+            ("2^63", Value::BigInt(two.pow(63))),
+            ("9223372036854775807 + 1", Value::BigInt(two.pow(63))),
+            ("-9223372036854775807 - 1", Value::Integer(i64::MIN)),
+            ("-(-9223372036854775807 - 1)", Value::BigInt(two.pow(63))),
+            ("2^1000", Value::BigInt(two.pow(1000))),
+            ("2^1000 / 2^999", Value::Integer(2)),
+            ("2^64 % 10", Value::Integer(6)),
+            (
+                "2^64 / 3",
+                Value::Rational(BigRational::new(two.pow(64), 3.into())),
+            ),
+            (
+                "99999999999999999999 - 99999999999999999998",
+                Value::Integer(1),
+            ),
+            ("(-1)^(2^70 + 1)", Value::Integer(-1)),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(test_expr_parse(input).unwrap(), expected, "{}", input);
+        }
+        assert_eq!(
+            test_expr_parse("3^50").unwrap().to_string(),
+            "717897987691852588770249"
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        // (input, message, line, column)
+        let test_table = vec![
+            (
+                "5 + * 3",
+                "expected a number, a name or '(', found '*'",
+                1,
+                5,
+            ),
+            (
+                "5 +",
+                "expected a number, a name or '(', found end of input",
+                1,
+                4,
+            ),
+            (
+                "(1 + 2",
+                "expected an operator or ')', found end of input",
+                1,
+                7,
+            ),
+            (
+                "1 2",
+                "expected end of input or an operator, found '2'",
+                1,
+                3,
+            ),
+            (
+                "",
+                "expected a number, a name or '(', found end of input",
+                1,
+                1,
+            ),
+        ];
+        for (input, message, line, column) in test_table.into_iter() {
+            let err = parse(input).unwrap_err();
+            assert_eq!(err.to_string(), message, "{}", input);
+            assert_eq!((err.line, err.column), (line, column), "{}", input);
+        }
+    }
+
+    #[test]
+    fn render_parse_error() {
+        let err = parse("12 * / 4").unwrap_err();
+        assert_eq!(
+            err.render("12 * / 4"),
+            "error: expected a number, a name or '(', found '/'\n  \
+             --> 1:6\n  \
+             |\n\
+             1 | 12 * / 4\n  \
+             |      ^"
+        );
+    }
+
+    #[test]
+    fn too_deep() {
+        let nested = |n| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+        let conditionals = |n| format!("{}0", "if true then 1 else ".repeat(n));
+        let sum = |n: usize| format!("1{}", "+1".repeat(n - 1));
+        let powers = |n: usize| format!("1{}", "^1".repeat(n - 1));
+        let negated = |n| format!("{}1", "-".repeat(n));
+        let test_table = vec![
+            (
+                nested(3000),
+                "brackets and conditionals nest more than 100 deep",
+                1,
+                101,
+            ),
+            (
+                conditionals(3000),
+                "brackets and conditionals nest more than 100 deep",
+                1,
+                2001,
+            ),
+            (
+                powers(300),
+                "expression is more than 256 operations deep",
+                1,
+                1,
+            ),
+            (
+                negated(20000),
+                "expression is more than 256 operations deep",
+                1,
+                1,
+            ),
+            (
+                format!("2 * ({})", powers(300)),
+                "expression is more than 256 operations deep",
+                1,
+                6,
+            ),
+        ];
+        for (input, message, line, column) in test_table.into_iter() {
+            let err = parse(&input).unwrap_err();
+            assert_eq!(err.to_string(), message, "{}", input);
+            assert_eq!((err.line, err.column), (line, column), "{}", input);
+            assert!(parse_statement(&format!("x = {}", input)).is_err());
+        }
+
+        // Just under the limits still works
+        for (input, expected) in [
+            (nested(100), "1"),
+            (conditionals(100), "1"),
+            (powers(200), "1"),
+            (negated(200), "1"),
+            // Operations in a row don't nest, however many there are
+            (sum(50000), "50000"),
+            (format!("2 * ({}) - 1", sum(1000)), "1999"),
+            ("1 * 1 + ".repeat(20000) + "0", "20000"),
+            (
+                format!("{}1{}", "(1 + ".repeat(100), ")".repeat(100)),
+                "101",
+            ),
+        ] {
+            assert_eq!(
+                parse(&input)
+                    .unwrap()
+                    .eval(&Environment::new())
+                    .unwrap()
+                    .to_string(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn literal_out_of_range() {
+        let err = parse("1 + 1e999").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::LiteralOutOfRange {
+                literal: "1e999".to_string()
+            }
+        );
+        assert_eq!(err.span, Span::new(4, 9));
+        assert_eq!((err.line, err.column), (1, 5));
+        assert_eq!(err.to_string(), "number 1e999 is out of range");
+
+        let err = parse("$99999999999999999999").unwrap_err();
+        assert_eq!(err.span, Span::new(0, 21));
+        assert_eq!(
+            err.to_string(),
+            "number $99999999999999999999 is out of range"
+        );
     }
 }
@@ -1,8 +1,120 @@
-use calc::repl;
+use calc::{repl, Session};
+use std::fs::File;
+use std::io::{self, BufReader, IsTerminal};
+use std::process::ExitCode;
 
-fn main() {
-    if let Err(e) = repl() {
-        eprintln!("{}", e);
-        std::process::exit(1);
+const USAGE: &str = "\
+usage: calc                 start an interactive session
+       calc <expression>... evaluate the arguments as one line
+       calc -f <file>       run each line of a file
+       command | calc       run each line of standard input
+
+Exits with 1 when a line has an error, and 2 for bad usage.";
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq)]
+enum Mode {
+    /// A REPL, or a script from standard input if that isn't a terminal
+    Session,
+    Expression(String),
+    File(String),
+    Help,
+}
+
+fn parse_args(args: &[String]) -> Result<Mode, String> {
+    match args {
+        [] => Ok(Mode::Session),
+        [flag] if flag == "-h" || flag == "--help" => Ok(Mode::Help),
+        [flag, path] if flag == "-f" || flag == "--file" => Ok(Mode::File(path.clone())),
+        [flag, ..] if flag == "-f" || flag == "--file" => {
+            Err(format!("{} takes exactly one file", flag))
+        }
+        [flag, rest @ ..] if flag == "--" => Ok(Mode::Expression(rest.join(" "))),
+        [flag, ..] if is_option(flag) => Err(format!("unknown option '{}'", flag)),
+        args => Ok(Mode::Expression(args.join(" "))),
+    }
+}
+
+/// Whether `arg` looks like an option rather than the start of an
+/// expression like `-2 * 3`. Use `--` before an expression like `-x`.
+fn is_option(arg: &str) -> bool {
+    match arg.strip_prefix('-') {
+        Some(rest) => rest.starts_with(|c: char| c == '-' || c.is_ascii_alphabetic()),
+        None => false,
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = match parse_args(&args) {
+        Ok(mode) => mode,
+        Err(e) => {
+            eprintln!("calc: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let mut session = Session::new();
+    let (mut out, mut err) = (io::stdout(), io::stderr());
+    let result = match mode {
+        Mode::Help => {
+            println!("{}", USAGE);
+            Ok(true)
+        }
+        Mode::Session if io::stdin().is_terminal() => repl().map(|_| true),
+        Mode::Session => session.run_script(io::stdin().lock(), &mut out, &mut err),
+        Mode::Expression(line) => session.run_line(&line, &mut out, &mut err),
+        Mode::File(path) => match File::open(&path) {
+            Ok(file) => session.run_script(BufReader::new(file), &mut out, &mut err),
+            Err(e) => {
+                eprintln!("calc: can't read '{}': {}", path, e);
+                return ExitCode::from(2);
+            }
+        },
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("calc: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments() {
+        let test_table = vec![
+            (vec![], Ok(Mode::Session)),
+            (vec!["--help"], Ok(Mode::Help)),
+            (vec!["2^10"], Ok(Mode::Expression("2^10".to_string()))),
+            (
+                vec!["2", "+", "3"],
+                Ok(Mode::Expression("2 + 3".to_string())),
+            ),
+            (
+                vec!["-2", "*", "3"],
+                Ok(Mode::Expression("-2 * 3".to_string())),
+            ),
+            (vec!["--", "-x"], Ok(Mode::Expression("-x".to_string()))),
+            (vec!["-f", "a.calc"], Ok(Mode::File("a.calc".to_string()))),
+            (
+                vec!["-f", "a.calc", "b.calc"],
+                Err("-f takes exactly one file".to_string()),
+            ),
+            (vec!["-f"], Err("-f takes exactly one file".to_string())),
+            (vec!["-x"], Err("unknown option '-x'".to_string())),
+            (
+                vec!["--verbose"],
+                Err("unknown option '--verbose'".to_string()),
+            ),
+        ];
+        for (args, expected) in test_table.into_iter() {
+            let args: Vec<_> = args.into_iter().map(String::from).collect();
+            assert_eq!(parse_args(&args), expected, "{:?}", args);
+        }
     }
 }
@@ -0,0 +1,393 @@
+use crate::env::check_interrupt;
+use crate::error::{EvalError, Span};
+use crate::value::{MAX_POWER_BITS, MAX_RANGE_LEN};
+use crate::{Op, OverflowMode, Value};
+use num_traits::{Signed, ToPrimitive};
+use std::fmt;
+
+/// A rectangular grid of numbers, stored row by row. Vectors are matrices
+/// with a single row or column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    elements: Vec<Value>,
+}
+
+impl Matrix {
+    /// Panics unless there are `rows * cols` elements.
+    pub fn new(rows: usize, cols: usize, elements: Vec<Value>) -> Matrix {
+        assert_eq!(
+            elements.len(),
+            rows * cols,
+            "matrix is not {}x{}",
+            rows,
+            cols
+        );
+        Matrix {
+            rows,
+            cols,
+            elements,
+        }
+    }
+
+    fn identity(n: usize) -> Matrix {
+        let elements = (0..n * n)
+            .map(|i| Value::Integer((i % (n + 1) == 0) as i64))
+            .collect();
+        Matrix::new(n, n, elements)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// All elements, row by row.
+    pub fn elements(&self) -> &[Value] {
+        &self.elements
+    }
+
+    pub fn into_elements(self) -> Vec<Value> {
+        self.elements
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> &Value {
+        &self.elements[row * self.cols + col]
+    }
+
+    /// Whether the matrix has a single row or column.
+    pub fn is_vector(&self) -> bool {
+        (self.rows == 1 || self.cols == 1) && !self.elements.is_empty()
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let elements = (0..self.cols)
+            .flat_map(|col| (0..self.rows).map(move |row| (row, col)))
+            .map(|(row, col)| self.get(row, col).clone())
+            .collect();
+        Matrix::new(self.cols, self.rows, elements)
+    }
+
+    /// The shape as `rows`x`cols`, for error messages.
+    pub fn shape(&self) -> String {
+        format!("{}x{}", self.rows, self.cols)
+    }
+
+    fn row_vecs(&self) -> Vec<Vec<Value>> {
+        if self.cols == 0 {
+            return vec![Vec::new(); self.rows];
+        }
+        self.elements
+            .chunks(self.cols)
+            .map(<[Value]>::to_vec)
+            .collect()
+    }
+
+    pub(crate) fn map(
+        self,
+        f: impl FnMut(Value) -> Result<Value, EvalError>,
+    ) -> Result<Matrix, EvalError> {
+        let elements = self.elements.into_iter().map(f).collect::<Result<_, _>>()?;
+        Ok(Matrix { elements, ..self })
+    }
+
+    /// Writes the matrix as it would be typed, using `element` for each
+    /// value, e.g. `[1, 2; 3, 4]`.
+    pub(crate) fn render(&self, element: impl Fn(&Value) -> String) -> String {
+        let rows: Vec<_> = self
+            .row_vecs()
+            .iter()
+            .map(|row| row.iter().map(&element).collect::<Vec<_>>().join(", "))
+            .collect();
+        format!("[{}]", rows.join("; "))
+    }
+}
+
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render(Value::to_string))
+    }
+}
+
+/// Applies `op` where either side is a matrix. `*`, `/` and `^` between
+/// matrices follow the rules of matrix algebra; other operators, and any
+/// operator with a number on one side, apply element by element.
+pub(crate) fn binary_op(
+    lhs: Value,
+    op: &Op,
+    rhs: Value,
+    mode: OverflowMode,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let expected = match op {
+        Op::And | Op::Or => Some("a boolean"),
+        Op::Less | Op::LessEqual | Op::Greater | Op::GreaterEqual => Some("a number"),
+        Op::Equal | Op::NotEqual => {
+            let equal = equal(lhs.expect_matrix(span)?, rhs.expect_matrix(span)?, span)?;
+            return Ok(Value::Bool(equal == matches!(op, Op::Equal)));
+        }
+        Op::Power => return power(lhs, &rhs, mode, span).map(Value::Matrix),
+        _ => None,
+    };
+    if let Some(expected) = expected {
+        return Err(EvalError::TypeMismatch {
+            expected,
+            found: "a matrix",
+            span,
+        });
+    }
+    let result = match (lhs, rhs) {
+        (Value::Matrix(lhs), Value::Matrix(rhs)) => match op {
+            Op::Multiply => product(&lhs, &rhs, mode, span)?,
+            Op::Divide => product(&lhs, &inverse(&rhs, mode, span)?, mode, span)?,
+            _ if lhs.rows != rhs.rows || lhs.cols != rhs.cols => {
+                return Err(mismatch(&lhs, &rhs, span));
+            }
+            _ => {
+                let mut rhs = rhs.elements.into_iter();
+                lhs.map(|val| arith(val, op.clone(), rhs.next().unwrap(), mode, span))?
+            }
+        },
+        // Dividing by a matrix multiplies by its inverse
+        (lhs, Value::Matrix(rhs)) if matches!(op, Op::Divide) => inverse(&rhs, mode, span)?
+            .map(|val| arith(lhs.clone(), Op::Multiply, val, mode, span))?,
+        (lhs, Value::Matrix(rhs)) => {
+            rhs.map(|val| arith(lhs.clone(), op.clone(), val, mode, span))?
+        }
+        (Value::Matrix(lhs), rhs) => {
+            lhs.map(|val| arith(val, op.clone(), rhs.clone(), mode, span))?
+        }
+        (lhs, _) => return Err(lhs.type_mismatch("a matrix", span)),
+    };
+    Ok(Value::Matrix(result))
+}
+
+fn mismatch(lhs: &Matrix, rhs: &Matrix, span: Span) -> EvalError {
+    EvalError::ShapeMismatch {
+        lhs: lhs.shape(),
+        rhs: rhs.shape(),
+        span,
+    }
+}
+
+/// Whether the matrices have the same shape and equal elements.
+fn equal(lhs: &Matrix, rhs: &Matrix, span: Span) -> Result<bool, EvalError> {
+    if lhs.rows != rhs.rows || lhs.cols != rhs.cols {
+        return Ok(false);
+    }
+    for (lhs, rhs) in lhs.elements.iter().zip(&rhs.elements) {
+        let equal =
+            lhs.clone()
+                .binary_op(&Op::Equal, rhs.clone(), OverflowMode::default(), span)?;
+        if equal != Value::Bool(true) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn arith(
+    lhs: Value,
+    op: Op,
+    rhs: Value,
+    mode: OverflowMode,
+    span: Span,
+) -> Result<Value, EvalError> {
+    check_interrupt(span)?;
+    lhs.binary_op(&op, rhs, mode, span)
+}
+
+pub(crate) fn product(
+    lhs: &Matrix,
+    rhs: &Matrix,
+    mode: OverflowMode,
+    span: Span,
+) -> Result<Matrix, EvalError> {
+    if lhs.cols != rhs.rows {
+        return Err(mismatch(lhs, rhs, span));
+    }
+    // Same limit as ranges, so `transpose(1..n) * (1..n)` can't ask for
+    // more memory than there is
+    let len = lhs
+        .rows
+        .checked_mul(rhs.cols)
+        .filter(|&len| len as u64 <= MAX_RANGE_LEN)
+        .ok_or(EvalError::Overflow { span })?;
+    let mut elements = Vec::with_capacity(len);
+    for row in 0..lhs.rows {
+        for col in 0..rhs.cols {
+            let mut sum = Value::Integer(0);
+            for k in 0..lhs.cols {
+                let term = arith(
+                    lhs.get(row, k).clone(),
+                    Op::Multiply,
+                    rhs.get(k, col).clone(),
+                    mode,
+                    span,
+                )?;
+                sum = arith(sum, Op::Add, term, mode, span)?;
+            }
+            elements.push(sum);
+        }
+    }
+    Ok(Matrix::new(lhs.rows, rhs.cols, elements))
+}
+
+/// A square matrix raised to an integer power. Negative powers are powers of
+/// the inverse, and elements may grow no bigger than `^` lets a number grow.
+fn power(
+    base: Value,
+    exponent: &Value,
+    mode: OverflowMode,
+    span: Span,
+) -> Result<Matrix, EvalError> {
+    let base = base.expect_matrix(span)?;
+    let exponent = exponent.expect_integer(span)?.to_bigint();
+    square(base, span)?;
+    let mut base = if exponent.is_negative() {
+        inverse(base, mode, span)?
+    } else {
+        base.clone()
+    };
+    let mut exponent = exponent
+        .magnitude()
+        .to_u64()
+        .ok_or(EvalError::Overflow { span })?;
+    let mut result = Matrix::identity(base.rows);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = bounded(product(&result, &base, mode, span)?, span)?;
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = bounded(product(&base, &base, mode, span)?, span)?;
+        }
+    }
+    Ok(result)
+}
+
+/// Fails if any exact element has outgrown `MAX_POWER_BITS`. Repeated squaring
+/// doubles the size each time, so this stops a power within a few steps of
+/// the limit.
+fn bounded(matrix: Matrix, span: Span) -> Result<Matrix, EvalError> {
+    let too_big = matrix.elements.iter().any(|element| {
+        let bits = match element {
+            Value::BigInt(val) => val.bits(),
+            Value::Rational(val) => val.numer().bits() + val.denom().bits(),
+            _ => 0,
+        };
+        bits > MAX_POWER_BITS
+    });
+    if too_big {
+        Err(EvalError::Overflow { span })
+    } else {
+        Ok(matrix)
+    }
+}
+
+fn square(matrix: &Matrix, span: Span) -> Result<usize, EvalError> {
+    if matrix.rows == matrix.cols {
+        Ok(matrix.rows)
+    } else {
+        Err(EvalError::NotSquare {
+            shape: matrix.shape(),
+            span,
+        })
+    }
+}
+
+fn is_zero(value: &Value) -> bool {
+    !matches!(value, Value::Complex(_)) && value.cmp_numeric(&Value::Integer(0)).is_eq()
+}
+
+/// Gauss-Jordan elimination on `rows`, whose first `n` columns form a square
+/// matrix. Returns the determinant of that matrix; when it isn't zero, the
+/// first `n` columns are left as the identity and the rest hold the
+/// solution to the system.
+fn eliminate(
+    rows: &mut [Vec<Value>],
+    n: usize,
+    mode: OverflowMode,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let mut det = Value::Integer(1);
+    for col in 0..n {
+        // The largest pivot keeps rounding errors small for reals
+        let magnitude = |row: &usize| rows[*row][col].to_complex().norm();
+        let pivot = (col..n)
+            .max_by(|a, b| magnitude(a).total_cmp(&magnitude(b)))
+            .unwrap();
+        if is_zero(&rows[pivot][col]) {
+            return Ok(Value::Integer(0));
+        }
+        if pivot != col {
+            rows.swap(pivot, col);
+            det = det.negate(mode, span)?;
+        }
+        let pivot = rows[col][col].clone();
+        det = arith(det, Op::Multiply, pivot.clone(), mode, span)?;
+        for val in rows[col].iter_mut().skip(col) {
+            *val = arith(val.clone(), Op::Divide, pivot.clone(), mode, span)?;
+        }
+        let pivot_row = rows[col].clone();
+        for (index, row) in rows.iter_mut().enumerate() {
+            let factor = row[col].clone();
+            if index == col || is_zero(&factor) {
+                continue;
+            }
+            for (val, pivot_val) in row.iter_mut().zip(&pivot_row).skip(col) {
+                let term = arith(factor.clone(), Op::Multiply, pivot_val.clone(), mode, span)?;
+                *val = arith(val.clone(), Op::Subtract, term, mode, span)?;
+            }
+        }
+    }
+    Ok(det)
+}
+
+pub(crate) fn determinant(
+    matrix: &Matrix,
+    mode: OverflowMode,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let n = square(matrix, span)?;
+    eliminate(&mut matrix.row_vecs(), n, mode, span)
+}
+
+/// Solves `lhs * x = rhs` for `x`, where `rhs` has a column for each
+/// system to solve.
+pub(crate) fn solve(
+    lhs: &Matrix,
+    rhs: &Matrix,
+    mode: OverflowMode,
+    span: Span,
+) -> Result<Matrix, EvalError> {
+    let n = square(lhs, span)?;
+    if rhs.rows != n {
+        return Err(mismatch(lhs, rhs, span));
+    }
+    let mut rows = lhs.row_vecs();
+    for (row, extra) in rows.iter_mut().zip(rhs.row_vecs()) {
+        row.extend(extra);
+    }
+    if is_zero(&eliminate(&mut rows, n, mode, span)?) {
+        return Err(EvalError::SingularMatrix { span });
+    }
+    let elements = rows
+        .into_iter()
+        .flat_map(|row| row.into_iter().skip(n))
+        .collect();
+    Ok(Matrix::new(n, rhs.cols, elements))
+}
+
+pub(crate) fn inverse(
+    matrix: &Matrix,
+    mode: OverflowMode,
+    span: Span,
+) -> Result<Matrix, EvalError> {
+    let n = square(matrix, span)?;
+    solve(matrix, &Matrix::identity(n), mode, span)
+}
@@ -0,0 +1,716 @@
+use crate::editor::Editor;
+use crate::{
+    parse_statement_with, tokens, Environment, NumberFormat, Statement, Syntax, CONSTANTS,
+};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by Ctrl-C, which stops the calculation in progress.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Session commands and what they do, for `:help`.
+const COMMANDS: &[(&str, &str)] = &[
+    (":help", "show this list"),
+    (":format [name]", "show or set how results are written"),
+    (
+        ":overflow [mode]",
+        "show or set how fixed-width integers overflow",
+    ),
+    (
+        ":syntax [relaxed|strict]",
+        "show or set whether juxtaposition multiplies",
+    ),
+    (
+        ":ast [on|off]",
+        "show or set whether each line's syntax tree is shown",
+    ),
+    (":mode", "show all of the settings above"),
+    (":tokens <input>", "show how the grammar splits up a line"),
+    (":vars", "list variables and functions"),
+    (":constants", "list built-in constants"),
+    (":history", "list earlier inputs and their results"),
+    (":reset", "forget variables, functions and results"),
+    (":load <file>", "run each line of a file"),
+    (":quit", "leave, as do exit and Ctrl-D"),
+];
+
+/// State carried between lines of a REPL session.
+#[derive(Debug, Default)]
+pub struct Session {
+    env: Environment,
+    format: NumberFormat,
+    syntax: Syntax,
+    /// The input behind each recorded result, so `history[0]` gave `$1`.
+    history: Vec<String>,
+    /// Whether to show the syntax tree of each line before its result
+    show_ast: bool,
+    /// The files `:load` is running, innermost last, so a file can't load
+    /// itself over and over
+    loading: Vec<PathBuf>,
+    finished: bool,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Whether the user has asked to leave, with `:quit` or `exit`.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Runs one line of input, writing results to `out` and problems to
+    /// `err`. Lines starting with `:` are session commands, and blank lines
+    /// do nothing. Returns whether the line ran without problems.
+    pub fn run_line(
+        &mut self,
+        line: &str,
+        out: &mut impl Write,
+        err: &mut impl Write,
+    ) -> io::Result<bool> {
+        let mut err = Problems {
+            inner: err,
+            reported: false,
+        };
+        self.execute_line(line, out, &mut err)?;
+        Ok(!err.reported)
+    }
+
+    /// Runs each line of `input` without prompting, stopping after the
+    /// first that has a problem. Returns whether every line ran cleanly.
+    pub fn run_script(
+        &mut self,
+        input: impl BufRead,
+        out: &mut impl Write,
+        err: &mut impl Write,
+    ) -> io::Result<bool> {
+        for line in input.lines() {
+            if self.finished {
+                break;
+            }
+            if !self.run_line(&line?, out, err)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn execute_line(
+        &mut self,
+        line: &str,
+        out: &mut impl Write,
+        err: &mut impl Write,
+    ) -> io::Result<()> {
+        if let Some(command) = line.trim_start().strip_prefix(':') {
+            return self.run_command(command, out, err);
+        }
+        match line.trim() {
+            "" => return Ok(()),
+            // On its own, `exit` leaves rather than looking up a variable
+            "exit" => {
+                self.finished = true;
+                return Ok(());
+            }
+            _ => {}
+        }
+        match parse_statement_with(line, self.syntax) {
+            Ok(inner) => {
+                if self.show_ast {
+                    write!(out, "{}", inner.tree())?;
+                }
+                let (statement, format) = match &inner {
+                    Statement::Display { statement, format } => (&**statement, *format),
+                    statement => (statement, self.format),
+                };
+                let result = statement.execute(&mut self.env);
+                if let Ok(Some(value)) = &result {
+                    self.env.push_result(value.clone());
+                    self.history.push(line.to_string());
+                }
+                match result {
+                    Ok(value) => match (statement, value) {
+                        (Statement::Define { name, function }, _) => {
+                            writeln!(out, "defined {}({})", name, function.params.join(", "))
+                        }
+                        (Statement::Assign { name, .. }, Some(value)) => {
+                            writeln!(out, "{} = {}", name, value.format(format))
+                        }
+                        (_, Some(value)) => writeln!(out, "{}", value.format(format)),
+                        (_, None) => Ok(()),
+                    },
+                    Err(e) => writeln!(err, "Evaluation failed: {}", e),
+                }
+            }
+            Err(e) => writeln!(err, "{}", e.render(line)),
+        }
+    }
+
+    fn run_command(
+        &mut self,
+        command: &str,
+        out: &mut impl Write,
+        err: &mut impl Write,
+    ) -> io::Result<()> {
+        let command = command.trim();
+        // Everything after the name is its argument, which may have spaces
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, Some(arg.trim())),
+            None => (command, None),
+        };
+        match (name, arg) {
+            ("help", None) => {
+                let width = COMMANDS.iter().map(|(usage, _)| usage.len()).max();
+                for (usage, description) in COMMANDS {
+                    writeln!(
+                        out,
+                        "{:width$}  {}",
+                        usage,
+                        description,
+                        width = width.unwrap()
+                    )?;
+                }
+                Ok(())
+            }
+            ("format", None) => writeln!(out, "{}", self.format),
+            ("format", Some(name)) => match name.parse() {
+                Ok(format) => {
+                    self.format = format;
+                    Ok(())
+                }
+                Err(e) => writeln!(err, "{}", e),
+            },
+            ("overflow", None) => writeln!(out, "{}", self.env.overflow_mode()),
+            ("overflow", Some(name)) => match name.parse() {
+                Ok(mode) => {
+                    self.env.set_overflow_mode(mode);
+                    Ok(())
+                }
+                Err(e) => writeln!(err, "{}", e),
+            },
+            ("syntax", None) => writeln!(out, "{}", self.syntax),
+            ("syntax", Some(name)) => match name.parse() {
+                Ok(syntax) => {
+                    self.syntax = syntax;
+                    Ok(())
+                }
+                Err(e) => writeln!(err, "{}", e),
+            },
+            ("ast", None) => {
+                writeln!(out, "{}", if self.show_ast { "on" } else { "off" })
+            }
+            ("ast", Some(setting @ ("on" | "off"))) => {
+                self.show_ast = setting == "on";
+                Ok(())
+            }
+            ("ast", Some(setting)) => {
+                writeln!(err, "unknown setting '{}', expected on or off", setting)
+            }
+            ("mode", None) => {
+                writeln!(out, "format: {}", self.format)?;
+                writeln!(out, "overflow: {}", self.env.overflow_mode())?;
+                writeln!(out, "syntax: {}", self.syntax)?;
+                writeln!(out, "ast: {}", if self.show_ast { "on" } else { "off" })
+            }
+            ("tokens", Some(input)) => match tokens(input) {
+                Ok(tokens) => {
+                    for (rule, text) in tokens {
+                        writeln!(out, "{:?} {:?}", rule, text)?;
+                    }
+                    Ok(())
+                }
+                Err(e) => writeln!(err, "{}", e.render(input)),
+            },
+            ("vars", None) => {
+                for (name, value) in self.env.variables() {
+                    writeln!(out, "{} = {}", name, value.format(self.format))?;
+                }
+                for (name, function) in self.env.functions() {
+                    writeln!(out, "{}({})", name, function.params.join(", "))?;
+                }
+                Ok(())
+            }
+            ("reset", None) => {
+                self.env.clear();
+                self.history.clear();
+                Ok(())
+            }
+            ("load", Some(path)) => {
+                // The same file can be named by different paths
+                let file = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+                if self.loading.contains(&file) {
+                    return writeln!(err, "can't load '{}' while it's already loading", path);
+                }
+                let contents = match fs::read_to_string(path) {
+                    Ok(contents) => contents,
+                    Err(e) => return writeln!(err, "can't read '{}': {}", path, e),
+                };
+                self.loading.push(file);
+                let mut result = Ok(());
+                for line in contents.lines() {
+                    if self.finished || result.is_err() {
+                        break;
+                    }
+                    result = self.execute_line(line, out, err);
+                }
+                self.loading.pop();
+                result
+            }
+            ("quit" | "q", None) => {
+                self.finished = true;
+                Ok(())
+            }
+            ("history", None) => {
+                for (index, input) in self.history.iter().enumerate() {
+                    let number = index + 1;
+                    let value = self.env.result(number).unwrap();
+                    writeln!(
+                        out,
+                        "${}: {} => {}",
+                        number,
+                        input,
+                        value.format(self.format)
+                    )?;
+                }
+                Ok(())
+            }
+            ("constants", None) => {
+                for constant in CONSTANTS {
+                    let value = constant.value().format(self.format);
+                    writeln!(
+                        out,
+                        "{} = {} ({})",
+                        constant.name, value, constant.description
+                    )?;
+                }
+                Ok(())
+            }
+            (name, _) => match COMMANDS
+                .iter()
+                .find(|(usage, _)| usage[1..].split(' ').next() == Some(name))
+            {
+                Some((usage, _)) => writeln!(err, "usage: {}", usage),
+                None => writeln!(err, "unknown command ':{}'", command),
+            },
+        }
+    }
+}
+
+/// Passes writes through to `inner`, noting whether there were any.
+struct Problems<'a, W> {
+    inner: &'a mut W,
+    reported: bool,
+}
+
+impl<W: Write> Write for Problems<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.reported |= !buf.is_empty();
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn repl() -> io::Result<()> {
+    const PROMPT: &str = ">> ";
+
+    let mut session = Session::new();
+    session.env.set_interrupt_flag(&INTERRUPTED);
+    #[cfg(unix)]
+    catch_interrupts();
+    let mut editor = Editor::new();
+    while !session.is_finished() {
+        let mut buffer = String::new();
+        if editor.read_line(PROMPT, &mut buffer)? == 0 {
+            // Ctrl-D or the end of piped input
+            break;
+        }
+        INTERRUPTED.store(false, Ordering::Relaxed);
+        let line = buffer.trim();
+        session.run_line(line, &mut io::stdout(), &mut io::stderr())?;
+    }
+    Ok(())
+}
+
+/// Makes Ctrl-C set `INTERRUPTED` instead of ending the process.
+#[cfg(unix)]
+fn catch_interrupts() {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+    // SAFETY: the handler only stores to an atomic, which is safe to do
+    // from a signal handler
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as *const () as libc::sighandler_t,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(session: &mut Session, line: &str) -> (String, String) {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        session.run_line(line, &mut out, &mut err).unwrap();
+        (
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    #[test]
+    fn bindings_persist() {
+        let mut session = Session::new();
+        assert_eq!(run(&mut session, "x = 3 * 4").0, "x = 12\n");
+        assert_eq!(run(&mut session, "y = x / 8").0, "y = 3/2\n");
+        assert_eq!(run(&mut session, "x + y").0, "27/2\n");
+        assert_eq!(run(&mut session, "x = x + 1").0, "x = 13\n");
+
+        let (_, err) = run(&mut session, "x + z");
+        assert_eq!(err, "Evaluation failed: undefined variable 'z'\n");
+    }
+
+    #[test]
+    fn function_definitions() {
+        let mut session = Session::new();
+        let (out, _) = run(&mut session, "area(w, h) = w * h");
+        assert_eq!(out, "defined area(w, h)\n");
+        assert_eq!(run(&mut session, "area(3, 4)").0, "12\n");
+    }
+
+    #[test]
+    fn format_command() {
+        let mut session = Session::new();
+        assert_eq!(run(&mut session, "1/3 + 1/6").0, "1/2\n");
+        assert_eq!(run(&mut session, ":format").0, "fraction\n");
+
+        run(&mut session, ":format decimal");
+        assert_eq!(run(&mut session, "1/4").0, "0.25\n");
+        assert_eq!(run(&mut session, ":format").0, "decimal\n");
+
+        let (_, err) = run(&mut session, ":format roman");
+        assert_eq!(
+            err,
+            "unknown format 'roman', expected one of: fraction, decimal, hex, binary, \
+             octal, scientific, engineering, all\n"
+        );
+        assert_eq!(run(&mut session, ":bogus").1, "unknown command ':bogus'\n");
+    }
+
+    #[test]
+    fn display_suffix() {
+        let mut session = Session::new();
+        assert_eq!(run(&mut session, "255 in hex").0, "0xff\n");
+        let (out, _) = run(&mut session, "mask = 0xf0 | 0x0f in bin");
+        assert_eq!(out, "mask = 0b11111111\n");
+        // The suffix only applies to its own line
+        assert_eq!(run(&mut session, "mask").0, "255\n");
+
+        run(&mut session, ":format hex");
+        assert_eq!(run(&mut session, "mask + 1").0, "0x100\n");
+        assert_eq!(run(&mut session, "1/4 in decimal").0, "0.25\n");
+        assert_eq!(run(&mut session, "1e6 in eng").0, "1e6\n");
+
+        let (_, err) = run(&mut session, "1 in roman");
+        assert!(err.starts_with("error: unknown format 'roman'"));
+    }
+
+    #[test]
+    fn help_command() {
+        let mut session = Session::new();
+        let (out, _) = run(&mut session, ":help");
+        assert_eq!(out.lines().count(), COMMANDS.len());
+        assert!(out.starts_with(":help                     show this list\n"));
+        assert!(out.contains("\n:load <file>              run each line of a file\n"));
+
+        assert_eq!(run(&mut session, ":load").1, "usage: :load <file>\n");
+        assert_eq!(run(&mut session, ":tokens").1, "usage: :tokens <input>\n");
+        assert_eq!(run(&mut session, ":vars x").1, "usage: :vars\n");
+        assert_eq!(run(&mut session, ""), (String::new(), String::new()));
+    }
+
+    #[test]
+    fn ast_command() {
+        let mut session = Session::new();
+        assert_eq!(run(&mut session, "1 + 2").0, "3\n");
+        assert_eq!(run(&mut session, ":ast").0, "off\n");
+
+        run(&mut session, ":ast on");
+        assert_eq!(
+            run(&mut session, "x = -2").0,
+            "Assign x\n└── UnaryMinus\n    └── Integer 2\nx = -2\n"
+        );
+        assert_eq!(run(&mut session, ":ast").0, "on\n");
+        run(&mut session, ":ast off");
+        assert_eq!(run(&mut session, "x").0, "-2\n");
+
+        let (_, err) = run(&mut session, ":ast yes");
+        assert_eq!(err, "unknown setting 'yes', expected on or off\n");
+    }
+
+    #[test]
+    fn tokens_command() {
+        let mut session = Session::new();
+        let (out, _) = run(&mut session, ":tokens 2x + sqrt(4)");
+        assert_eq!(
+            out,
+            "integer \"2\"\n\
+             unit_name \"x\"\n\
+             add \"+\"\n\
+             ident \"sqrt\"\n\
+             integer \"4\"\n"
+        );
+        let (_, err) = run(&mut session, ":tokens 1 +");
+        assert!(err.starts_with("error: expected a number, a name or '(', found end of input"));
+    }
+
+    #[test]
+    fn vars_and_reset() {
+        let mut session = Session::new();
+        run(&mut session, "y = 1/2");
+        run(&mut session, "x = 3");
+        run(&mut session, "f(a, b) = a + b");
+        run(&mut session, "x + 1");
+        assert_eq!(run(&mut session, ":vars").0, "x = 3\ny = 1/2\nf(a, b)\n");
+
+        run(&mut session, ":format decimal");
+        run(&mut session, ":syntax strict");
+        run(&mut session, ":reset");
+        assert_eq!(run(&mut session, ":vars").0, "");
+        assert_eq!(run(&mut session, ":history").0, "");
+        assert_eq!(
+            run(&mut session, "ans").1,
+            "Evaluation failed: undefined variable 'ans'\n"
+        );
+        // Settings survive a reset
+        assert_eq!(
+            run(&mut session, ":mode").0,
+            "format: decimal\noverflow: checked\nsyntax: strict\nast: off\n"
+        );
+    }
+
+    #[test]
+    fn load_command() {
+        let path = std::env::temp_dir().join(format!("calc-load-{}.calc", std::process::id()));
+        fs::write(&path, "r = 2\n\narea(r) = pi * r^2\narea(r) in sci\n1 +\n").unwrap();
+        let mut session = Session::new();
+        let (out, err) = run(&mut session, &format!(":load {}", path.display()));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(out, "r = 2\ndefined area(r)\n1.2566370614359172e1\n");
+        assert!(err.starts_with("error: expected a number, a name or '(', found end of input"));
+        assert_eq!(run(&mut session, "r").0, "2\n");
+
+        let (_, err) = run(&mut session, ":load /nonexistent/file.calc");
+        assert!(err.starts_with("can't read '/nonexistent/file.calc': "));
+
+        // Files that load each other stop rather than recursing forever
+        let dir = std::env::temp_dir();
+        let first = dir.join(format!("calc-load-a-{}.calc", std::process::id()));
+        let second = dir.join(format!("calc-load-b-{}.calc", std::process::id()));
+        fs::write(&first, format!("1\n:load {}\n2\n", second.display())).unwrap();
+        fs::write(&second, format!("3\n:load {}\n", first.display())).unwrap();
+        let (out, err) = run(&mut session, &format!(":load {}", first.display()));
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+        assert_eq!(out, "1\n3\n2\n");
+        assert_eq!(
+            err,
+            format!(
+                "can't load '{}' while it's already loading\n",
+                first.display()
+            )
+        );
+        assert!(session.loading.is_empty());
+    }
+
+    #[test]
+    fn scripts() {
+        let mut session = Session::new();
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let script = "x = 2\n\n:format hex\nx * 8\n";
+        let ok = session
+            .run_script(script.as_bytes(), &mut out, &mut err)
+            .unwrap();
+        assert!(ok);
+        assert_eq!(String::from_utf8(out).unwrap(), "x = 2\n0x10\n");
+        assert!(err.is_empty());
+
+        // Windows line endings and indentation are fine
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let script = "x = 2\r\n\t:format hex\r\n  \t\r\n\tx *\t8\r";
+        let ok = Session::new()
+            .run_script(script.as_bytes(), &mut out, &mut err)
+            .unwrap();
+        assert_eq!(String::from_utf8(err).unwrap(), "");
+        assert!(ok);
+        assert_eq!(String::from_utf8(out).unwrap(), "x = 2\n0x10\n");
+
+        // Scripts stop at the first problem, and at exit
+        for (script, expected, clean) in [
+            ("1\n1 / 0\n2\n", "1\n", false),
+            ("1\n:format roman\n2\n", "1\n", false),
+            ("1\nexit\n2\n", "1\n", true),
+        ] {
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            let ok = Session::new()
+                .run_script(script.as_bytes(), &mut out, &mut err)
+                .unwrap();
+            assert_eq!(ok, clean, "{}", script);
+            assert_eq!(String::from_utf8(out).unwrap(), expected, "{}", script);
+        }
+    }
+
+    #[test]
+    fn quit() {
+        for line in [":quit", ":q", "exit", "  exit "] {
+            let mut session = Session::new();
+            assert!(!session.is_finished());
+            assert_eq!(run(&mut session, line), (String::new(), String::new()));
+            assert!(session.is_finished(), "{}", line);
+        }
+        // A variable called exit can still be used in expressions
+        let mut session = Session::new();
+        run(&mut session, "exit = 2");
+        assert_eq!(run(&mut session, "exit + 1").0, "3\n");
+        assert!(!session.is_finished());
+    }
+
+    #[test]
+    fn interrupt() {
+        use crate::error::{EvalError, Span};
+        use crate::Value;
+
+        static FLAG: AtomicBool = AtomicBool::new(false);
+        let mut session = Session::new();
+        session.env.set_interrupt_flag(&FLAG);
+        run(
+            &mut session,
+            "f(n) = if n < 2 then n else f(n - 1) + f(n - 2)",
+        );
+        FLAG.store(true, Ordering::Relaxed);
+        let (_, err) = run(&mut session, "f(20)");
+        assert_eq!(err, "Evaluation failed: interrupted\n");
+        FLAG.store(false, Ordering::Relaxed);
+        assert_eq!(run(&mut session, "f(10)").0, "55\n");
+
+        // Built-in functions see the flag too, without going back through
+        // the evaluator
+        let product = crate::functions::lookup("product").unwrap();
+        let args = vec![Value::Integer(2), Value::Integer(3)];
+        FLAG.store(true, Ordering::Relaxed);
+        let result = {
+            let _guard = session.env.watch_interrupts();
+            product.call(args.clone(), Span::new(0, 1))
+        };
+        assert!(matches!(result, Err(EvalError::Interrupted { .. })));
+        FLAG.store(false, Ordering::Relaxed);
+        let _guard = session.env.watch_interrupts();
+        assert_eq!(
+            product.call(args, Span::new(0, 1)).unwrap().to_string(),
+            "6"
+        );
+    }
+
+    #[test]
+    fn history() {
+        let mut session = Session::new();
+        let (_, err) = run(&mut session, "ans + 1");
+        assert_eq!(err, "Evaluation failed: undefined variable 'ans'\n");
+
+        assert_eq!(run(&mut session, "3 * 4").0, "12\n");
+        assert_eq!(run(&mut session, "ans + 1").0, "13\n");
+        assert_eq!(run(&mut session, "_ * 2").0, "26\n");
+        assert_eq!(run(&mut session, "x = $1 + $2").0, "x = 25\n");
+        assert_eq!(run(&mut session, "2$3").0, "52\n");
+        // Definitions and errors don't record a result
+        run(&mut session, "f(x) = x");
+        run(&mut session, "1 / 0");
+        assert_eq!(run(&mut session, "255 in hex").0, "0xff\n");
+        assert_eq!(run(&mut session, "ans").0, "255\n");
+
+        let (_, err) = run(&mut session, "$0 + $9");
+        assert_eq!(err, "Evaluation failed: no result $0 yet\n");
+        // A variable called ans hides the previous result
+        run(&mut session, "ans = 1");
+        assert_eq!(run(&mut session, "7").0, "7\n");
+        assert_eq!(run(&mut session, "ans").0, "1\n");
+
+        run(&mut session, ":format hex");
+        let (out, _) = run(&mut session, ":history");
+        let expected = [
+            "$1: 3 * 4 => 0xc",
+            "$2: ans + 1 => 0xd",
+            "$3: _ * 2 => 0x1a",
+            "$4: x = $1 + $2 => 0x19",
+            "$5: 2$3 => 0x34",
+            "$6: 255 in hex => 0xff",
+            "$7: ans => 0xff",
+            "$8: ans = 1 => 0x1",
+            "$9: 7 => 0x7",
+            "$10: ans => 0x1",
+        ];
+        assert_eq!(out, expected.map(|line| format!("{}\n", line)).concat());
+    }
+
+    #[test]
+    fn constants_command() {
+        let mut session = Session::new();
+        let (out, _) = run(&mut session, ":constants");
+        assert!(out.starts_with(
+            "pi = 3.141592653589793 (ratio of a circle's circumference to its diameter)\n"
+        ));
+        assert!(out.contains("\nc = 299792458 m/s (speed of light in a vacuum)\n"));
+        assert_eq!(out.lines().count(), CONSTANTS.len());
+
+        run(&mut session, ":format sci");
+        let (out, _) = run(&mut session, ":constants");
+        assert!(out.contains("\nc = 2.99792458e8 m/s (speed of light in a vacuum)\n"));
+    }
+
+    #[test]
+    fn syntax_command() {
+        let mut session = Session::new();
+        run(&mut session, "x = 4");
+        assert_eq!(run(&mut session, "3x").0, "12\n");
+        assert_eq!(run(&mut session, ":syntax").0, "relaxed\n");
+
+        run(&mut session, ":syntax strict");
+        let (_, err) = run(&mut session, "2(x)");
+        assert!(err.starts_with("error: implicit multiplication isn't allowed in strict syntax"));
+        let (_, err) = run(&mut session, "3x");
+        assert!(err.starts_with("error: unknown unit 'x'"));
+        assert_eq!(run(&mut session, "3 * x").0, "12\n");
+
+        let (_, err) = run(&mut session, ":syntax loose");
+        assert_eq!(
+            err,
+            "unknown syntax 'loose', expected one of: relaxed, strict\n"
+        );
+    }
+
+    #[test]
+    fn overflow_command() {
+        let mut session = Session::new();
+        assert_eq!(run(&mut session, ":overflow").0, "checked\n");
+        let (_, err) = run(&mut session, "u8(250) + 10");
+        assert_eq!(err, "Evaluation failed: arithmetic overflow\n");
+
+        run(&mut session, ":overflow wrapping");
+        assert_eq!(run(&mut session, "u8(250) + 10").0, "4\n");
+        assert_eq!(run(&mut session, ":overflow").0, "wrapping\n");
+
+        let (_, err) = run(&mut session, ":overflow saturating");
+        assert_eq!(
+            err,
+            "unknown overflow mode 'saturating', expected one of: checked, wrapping\n"
+        );
+    }
+}
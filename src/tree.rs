@@ -0,0 +1,204 @@
+use crate::{Expr, Op, Statement, Value};
+
+/// A node of the drawn tree, which may be a statement or an expression.
+enum Node<'a> {
+    Statement(&'a Statement),
+    Expr(&'a Expr),
+    /// An operation of a chain, with its right side
+    Operation(&'a Op, &'a Expr),
+}
+
+impl<'a> Node<'a> {
+    /// What to write for this node, and the nodes below it.
+    fn describe(&self) -> (String, Vec<Node<'a>>) {
+        let expr = match *self {
+            Node::Statement(statement) => match statement {
+                Statement::Assign { name, expr } => {
+                    return (format!("Assign {}", name), vec![Node::Expr(expr)]);
+                }
+                Statement::Define { name, function } => {
+                    let label = format!("Define {}({})", name, function.params.join(", "));
+                    return (label, vec![Node::Expr(&function.body)]);
+                }
+                Statement::Display { statement, format } => {
+                    let label = format!("Display in {}", format);
+                    return (label, vec![Node::Statement(statement)]);
+                }
+                Statement::Expr(expr) => expr,
+            },
+            Node::Expr(expr) => expr,
+            Node::Operation(op, expr) => {
+                let (label, children) = Node::Expr(expr).describe();
+                return (format!("{} {}", op, label), children);
+            }
+        };
+        let leaf = |label: String| (label, Vec::new());
+        let unary = |label: &str, expr: &'a Expr| (label.to_string(), vec![Node::Expr(expr)]);
+        match expr {
+            Expr::Integer { value, .. } => leaf(format!("Integer {}", value)),
+            Expr::Real { value, .. } => leaf(format!("Real {}", Value::Real(*value))),
+            Expr::Bool { value, .. } => leaf(format!("Bool {}", value)),
+            Expr::Imaginary { value, .. } => leaf(format!("Imaginary {}i", Value::Real(*value))),
+            Expr::Quantity { value, unit, .. } => {
+                leaf(format!("Quantity {} {}", Value::Real(*value), unit))
+            }
+            // Shown as the quantity, which it is unless variables shadow
+            // its units
+            Expr::Juxtaposed { quantity, .. } => Node::Expr(quantity).describe(),
+            Expr::Variable { name, .. } => leaf(format!("Variable {}", name)),
+            Expr::History { index, .. } => leaf(format!("History ${}", index)),
+            Expr::Call { name, args, .. } => (
+                format!("Call {}", name),
+                args.iter().map(Node::Expr).collect(),
+            ),
+            Expr::UnaryMinus { expr, .. } => unary("UnaryMinus", expr),
+            Expr::Not { expr, .. } => unary("Not", expr),
+            Expr::BitNot { expr, .. } => unary("BitNot", expr),
+            Expr::Factorial { expr, .. } => unary("Factorial", expr),
+            Expr::Percent { expr, .. } => unary("Percent", expr),
+            Expr::Degrees { expr, .. } => unary("Degrees", expr),
+            Expr::Matrix {
+                rows,
+                cols,
+                elements,
+                ..
+            } => (
+                format!("Matrix {}x{}", rows, cols),
+                elements.iter().map(Node::Expr).collect(),
+            ),
+            Expr::Convert { expr, unit, .. } => {
+                (format!("Convert to {}", unit), vec![Node::Expr(expr)])
+            }
+            Expr::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => (
+                "If".to_string(),
+                vec![
+                    Node::Expr(condition),
+                    Node::Expr(then_branch),
+                    Node::Expr(else_branch),
+                ],
+            ),
+            Expr::BinOp { lhs, op, rhs, .. } => (
+                format!("BinOp {}", op),
+                vec![Node::Expr(lhs), Node::Expr(rhs)],
+            ),
+            Expr::Chain { first, rest, .. } => (
+                "Chain".to_string(),
+                std::iter::once(Node::Expr(first))
+                    .chain(rest.iter().map(|(op, rhs)| Node::Operation(op, rhs)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Writes the node on a line starting with `lead`, and its children
+    /// below it, each line of which starts with `indent`.
+    fn draw(&self, out: &mut String, lead: &str, indent: &str) {
+        let (label, children) = self.describe();
+        out.push_str(lead);
+        out.push_str(&label);
+        out.push('\n');
+        let count = children.len();
+        for (index, child) in children.iter().enumerate() {
+            let (branch, rest) = if index + 1 == count {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            child.draw(
+                out,
+                &format!("{}{}", indent, branch),
+                &format!("{}{}", indent, rest),
+            );
+        }
+    }
+}
+
+impl Statement {
+    /// Draws the statement as a tree with a node on each line, to show how
+    /// input was parsed.
+    pub fn tree(&self) -> String {
+        let mut out = String::new();
+        Node::Statement(self).draw(&mut out, "", "");
+        out
+    }
+}
+
+impl Expr {
+    /// Draws the expression as a tree with a node on each line.
+    pub fn tree(&self) -> String {
+        let mut out = String::new();
+        Node::Expr(self).draw(&mut out, "", "");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_statement;
+
+    #[test]
+    fn trees() {
+        let test_table = vec![
+            ("42", "Integer 42\n"),
+            (
+                "1 + 2 * -x",
+                "BinOp +\n\
+                 ├── Integer 1\n\
+                 └── BinOp *\n    \
+                     ├── Integer 2\n    \
+                     └── UnaryMinus\n        \
+                         └── Variable x\n",
+            ),
+            (
+                "1 + 2 - 3 * 4 * 5",
+                "Chain\n\
+                 ├── Integer 1\n\
+                 ├── + Integer 2\n\
+                 └── - Chain\n    \
+                     ├── Integer 3\n    \
+                     ├── * Integer 4\n    \
+                     └── * Integer 5\n",
+            ),
+            (
+                "f(a) = if a > 0 then sqrt(a) else 0",
+                "Define f(a)\n\
+                 └── If\n    \
+                     ├── BinOp >\n    \
+                     │   ├── Variable a\n    \
+                     │   └── Integer 0\n    \
+                     ├── Call sqrt\n    \
+                     │   └── Variable a\n    \
+                     └── Integer 0\n",
+            ),
+            (
+                "y = [1.5, 2i] in hex",
+                "Display in hex\n\
+                 └── Assign y\n    \
+                     └── Matrix 1x2\n        \
+                         ├── Real 1.5\n        \
+                         └── Imaginary 2i\n",
+            ),
+            (
+                "5! + 3 km to m",
+                "Convert to m\n\
+                 └── BinOp +\n    \
+                     ├── Factorial\n    \
+                     │   └── Integer 5\n    \
+                     └── Quantity 3 km\n",
+            ),
+        ];
+        for (input, expected) in test_table.into_iter() {
+            assert_eq!(
+                parse_statement(input).unwrap().tree(),
+                expected,
+                "{}",
+                input
+            );
+        }
+    }
+}
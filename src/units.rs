@@ -0,0 +1,409 @@
+use crate::error::{EvalError, Span};
+use crate::value::real;
+use crate::{Op, Value};
+use num_traits::ToPrimitive;
+use std::fmt;
+
+/// Exponents of the seven SI base dimensions, in the order length, mass,
+/// time, electric current, temperature, amount of substance and luminous
+/// intensity.
+pub type Dimension = [i64; 7];
+
+/// A named unit of measure.
+#[derive(Debug, PartialEq)]
+pub struct UnitDef {
+    pub name: &'static str,
+    /// How many SI base units, e.g. metres, make one of this unit.
+    pub factor: f64,
+    pub dimension: [i8; 7],
+}
+
+macro_rules! unit {
+    ($name:literal, $factor:expr, $dimension:expr) => {
+        UnitDef {
+            name: $name,
+            factor: $factor,
+            dimension: $dimension,
+        }
+    };
+}
+
+const LENGTH: [i8; 7] = [1, 0, 0, 0, 0, 0, 0];
+const MASS: [i8; 7] = [0, 1, 0, 0, 0, 0, 0];
+const TIME: [i8; 7] = [0, 0, 1, 0, 0, 0, 0];
+const CURRENT: [i8; 7] = [0, 0, 0, 1, 0, 0, 0];
+const TEMPERATURE: [i8; 7] = [0, 0, 0, 0, 1, 0, 0];
+const AMOUNT: [i8; 7] = [0, 0, 0, 0, 0, 1, 0];
+const LUMINOSITY: [i8; 7] = [0, 0, 0, 0, 0, 0, 1];
+const SPEED: [i8; 7] = [1, 0, -1, 0, 0, 0, 0];
+const AREA: [i8; 7] = [2, 0, 0, 0, 0, 0, 0];
+const VOLUME: [i8; 7] = [3, 0, 0, 0, 0, 0, 0];
+const FREQUENCY: [i8; 7] = [0, 0, -1, 0, 0, 0, 0];
+const FORCE: [i8; 7] = [1, 1, -2, 0, 0, 0, 0];
+const PRESSURE: [i8; 7] = [-1, 1, -2, 0, 0, 0, 0];
+const ENERGY: [i8; 7] = [2, 1, -2, 0, 0, 0, 0];
+const POWER: [i8; 7] = [2, 1, -3, 0, 0, 0, 0];
+const CHARGE: [i8; 7] = [0, 0, 1, 1, 0, 0, 0];
+const VOLTAGE: [i8; 7] = [2, 1, -3, -1, 0, 0, 0];
+const RESISTANCE: [i8; 7] = [2, 1, -3, -2, 0, 0, 0];
+const CAPACITANCE: [i8; 7] = [-2, -1, 4, 2, 0, 0, 0];
+
+/// The units that can follow a number literal or `to`. Temperatures are
+/// only in kelvin, since Celsius and Fahrenheit need an offset as well as
+/// a factor.
+pub const UNITS: &[UnitDef] = &[
+    // Length
+    unit!("m", 1.0, LENGTH),
+    unit!("km", 1e3, LENGTH),
+    unit!("cm", 1e-2, LENGTH),
+    unit!("mm", 1e-3, LENGTH),
+    unit!("um", 1e-6, LENGTH),
+    unit!("nm", 1e-9, LENGTH),
+    // `in` is taken by display suffixes like `255 in hex`
+    unit!("inch", 0.0254, LENGTH),
+    unit!("ft", 0.3048, LENGTH),
+    unit!("yd", 0.9144, LENGTH),
+    unit!("mi", 1609.344, LENGTH),
+    unit!("nmi", 1852.0, LENGTH),
+    // Mass
+    unit!("kg", 1.0, MASS),
+    unit!("g", 1e-3, MASS),
+    unit!("mg", 1e-6, MASS),
+    unit!("t", 1e3, MASS),
+    unit!("lb", 0.45359237, MASS),
+    unit!("oz", 0.028349523125, MASS),
+    // Time
+    unit!("s", 1.0, TIME),
+    unit!("ms", 1e-3, TIME),
+    unit!("us", 1e-6, TIME),
+    unit!("ns", 1e-9, TIME),
+    unit!("min", 60.0, TIME),
+    unit!("h", 3600.0, TIME),
+    unit!("day", 86400.0, TIME),
+    unit!("week", 604800.0, TIME),
+    // Julian year, as used in astronomy
+    unit!("yr", 31557600.0, TIME),
+    // Other base units
+    unit!("A", 1.0, CURRENT),
+    unit!("mA", 1e-3, CURRENT),
+    unit!("K", 1.0, TEMPERATURE),
+    unit!("mol", 1.0, AMOUNT),
+    unit!("cd", 1.0, LUMINOSITY),
+    // Speed
+    unit!("mph", 0.44704, SPEED),
+    unit!("kn", 1852.0 / 3600.0, SPEED),
+    // Area and volume
+    unit!("ha", 1e4, AREA),
+    unit!("acre", 4046.8564224, AREA),
+    unit!("L", 1e-3, VOLUME),
+    unit!("mL", 1e-6, VOLUME),
+    unit!("gal", 3.785411784e-3, VOLUME),
+    // Frequency
+    unit!("Hz", 1.0, FREQUENCY),
+    unit!("kHz", 1e3, FREQUENCY),
+    unit!("MHz", 1e6, FREQUENCY),
+    unit!("GHz", 1e9, FREQUENCY),
+    // Mechanics
+    unit!("N", 1.0, FORCE),
+    unit!("kN", 1e3, FORCE),
+    unit!("lbf", 4.4482216152605, FORCE),
+    unit!("Pa", 1.0, PRESSURE),
+    unit!("kPa", 1e3, PRESSURE),
+    unit!("bar", 1e5, PRESSURE),
+    unit!("atm", 101325.0, PRESSURE),
+    unit!("psi", 6894.757293168361, PRESSURE),
+    unit!("J", 1.0, ENERGY),
+    unit!("kJ", 1e3, ENERGY),
+    unit!("cal", 4.184, ENERGY),
+    unit!("kcal", 4184.0, ENERGY),
+    unit!("Wh", 3600.0, ENERGY),
+    unit!("kWh", 3.6e6, ENERGY),
+    unit!("eV", 1.602176634e-19, ENERGY),
+    unit!("W", 1.0, POWER),
+    unit!("kW", 1e3, POWER),
+    unit!("MW", 1e6, POWER),
+    unit!("hp", 745.6998715822702, POWER),
+    // Electricity
+    unit!("C", 1.0, CHARGE),
+    unit!("V", 1.0, VOLTAGE),
+    unit!("mV", 1e-3, VOLTAGE),
+    unit!("kV", 1e3, VOLTAGE),
+    unit!("ohm", 1.0, RESISTANCE),
+    unit!("kohm", 1e3, RESISTANCE),
+    unit!("F", 1.0, CAPACITANCE),
+    unit!("uF", 1e-6, CAPACITANCE),
+];
+
+/// A unit as written, like `km/h` or `m/s^2`: named units raised to
+/// powers. A unit with no terms is no unit at all.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Unit {
+    terms: Vec<(&'static UnitDef, i32)>,
+}
+
+impl Unit {
+    /// The unit from `UNITS` called `name`.
+    pub fn named(name: &str) -> Option<Unit> {
+        let def = UNITS.iter().find(|def| def.name == name)?;
+        Some(Unit {
+            terms: vec![(def, 1)],
+        })
+    }
+
+    /// How many SI base units make one of this unit.
+    pub fn factor(&self) -> f64 {
+        self.terms
+            .iter()
+            .map(|(def, exponent)| def.factor.powi(*exponent))
+            .product()
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        let mut dimension = [0; 7];
+        for (def, exponent) in &self.terms {
+            for (total, base) in dimension.iter_mut().zip(def.dimension) {
+                *total += i64::from(base) * i64::from(*exponent);
+            }
+        }
+        dimension
+    }
+
+    /// `self * other`, cancelling units that appear in both. `None` if an
+    /// exponent overflows.
+    pub fn mul(&self, other: &Unit) -> Option<Unit> {
+        let mut terms = self.terms.clone();
+        for (def, exponent) in &other.terms {
+            match terms.iter_mut().find(|(existing, _)| existing == def) {
+                Some((_, total)) => *total = total.checked_add(*exponent)?,
+                None => terms.push((*def, *exponent)),
+            }
+        }
+        terms.retain(|(_, exponent)| *exponent != 0);
+        Some(Unit { terms })
+    }
+
+    /// The unit raised to `exponent`. `None` if an exponent overflows.
+    pub fn pow(&self, exponent: i32) -> Option<Unit> {
+        let terms = self
+            .terms
+            .iter()
+            .map(|(def, own)| Some((*def, own.checked_mul(exponent)?)))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Unit { terms })
+    }
+
+    /// Names the unit for error messages, where a missing unit needs
+    /// spelling out.
+    fn describe(&self) -> String {
+        if self.terms.is_empty() {
+            "a plain number".to_string()
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let term = |f: &mut fmt::Formatter<'_>, name: &str, exponent: i32| match exponent {
+            1 => write!(f, "{}", name),
+            _ => write!(f, "{}^{}", name, exponent),
+        };
+        let mut numerator = self.terms.iter().filter(|(_, exponent)| *exponent > 0);
+        match numerator.next() {
+            Some((def, exponent)) => term(f, def.name, *exponent)?,
+            None => write!(f, "1")?,
+        }
+        for (def, exponent) in numerator {
+            write!(f, "*")?;
+            term(f, def.name, *exponent)?;
+        }
+        for (def, exponent) in self.terms.iter().filter(|(_, exponent)| *exponent < 0) {
+            write!(f, "/")?;
+            term(f, def.name, -exponent)?;
+        }
+        Ok(())
+    }
+}
+
+/// A number with a unit. The value is held in that unit, so `3 km` is 3
+/// with unit `km`, and only conversions scale it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: Unit,
+}
+
+impl Quantity {
+    /// Wraps the result of an operation. Units that cancel out, as in
+    /// `1 km / 1 m`, leave a plain number.
+    pub(crate) fn into_value(self, span: Span) -> Result<Value, EvalError> {
+        if self.unit.dimension() == [0; 7] {
+            return real(self.value * self.unit.factor(), span);
+        }
+        real(self.value, span)?;
+        Ok(Value::Quantity(self))
+    }
+
+    /// The value expressed in `unit`, which must have the same dimension.
+    fn value_in(&self, unit: &Unit) -> f64 {
+        self.value * (self.unit.factor() / unit.factor())
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", Value::Real(rounded(self.value)), self.unit)
+    }
+}
+
+/// Conversions pick up rounding noise in the last few digits, as in
+/// 96.56064000000001, so quantities are shown to 15 significant figures.
+pub(crate) fn rounded(value: f64) -> f64 {
+    format!("{:.14e}", value).parse().unwrap_or(value)
+}
+
+/// Treats a plain number as a quantity with no unit.
+fn quantity(value: Value, span: Span) -> Result<Quantity, EvalError> {
+    match value {
+        Value::Quantity(quantity) => Ok(quantity),
+        value => Ok(Quantity {
+            value: value.expect_real(span)?.to_f64(),
+            unit: Unit::default(),
+        }),
+    }
+}
+
+fn mismatch(lhs: &Unit, rhs: &Unit, span: Span) -> EvalError {
+    EvalError::DimensionMismatch {
+        lhs: lhs.describe(),
+        rhs: rhs.describe(),
+        span,
+    }
+}
+
+/// Applies `op` where either side is a quantity. Plain numbers have no
+/// dimension, so they scale quantities but can't be added to them.
+pub(crate) fn binary_op(lhs: Value, op: &Op, rhs: Value, span: Span) -> Result<Value, EvalError> {
+    let expected = match op {
+        Op::And | Op::Or => Some("a boolean"),
+        Op::BitAnd | Op::BitOr | Op::BitXor | Op::ShiftLeft | Op::ShiftRight => Some("an integer"),
+        Op::Power => return power(lhs, rhs, span),
+        _ => None,
+    };
+    if let Some(expected) = expected {
+        return Err(EvalError::TypeMismatch {
+            expected,
+            found: "a quantity",
+            span,
+        });
+    }
+    let (lhs, rhs) = (quantity(lhs, span)?, quantity(rhs, span)?);
+    let overflow = || EvalError::Overflow { span };
+    let result = match op {
+        Op::Multiply => Quantity {
+            value: lhs.value * rhs.value,
+            unit: lhs.unit.mul(&rhs.unit).ok_or_else(overflow)?,
+        },
+        Op::Divide if rhs.value == 0.0 => return Err(EvalError::DivisionByZero { span }),
+        Op::Divide => Quantity {
+            value: lhs.value / rhs.value,
+            unit: lhs
+                .unit
+                .mul(&rhs.unit.pow(-1).ok_or_else(overflow)?)
+                .ok_or_else(overflow)?,
+        },
+        // Everything else needs both sides in the same unit
+        _ if lhs.unit.dimension() != rhs.unit.dimension() => {
+            return Err(mismatch(&lhs.unit, &rhs.unit, span));
+        }
+        _ => {
+            let rhs = rhs.value_in(&lhs.unit);
+            let value = match op {
+                Op::Add => lhs.value + rhs,
+                Op::Subtract => lhs.value - rhs,
+                Op::Modulo if rhs == 0.0 => return Err(EvalError::DivisionByZero { span }),
+                Op::Modulo => lhs.value % rhs,
+                _ => {
+                    // Adding zero makes -0.0 equal to 0.0
+                    let ordering = (lhs.value + 0.0).total_cmp(&(rhs + 0.0));
+                    return Ok(Value::Bool(match op {
+                        Op::Equal => ordering.is_eq(),
+                        Op::NotEqual => ordering.is_ne(),
+                        Op::Less => ordering.is_lt(),
+                        Op::LessEqual => ordering.is_le(),
+                        Op::Greater => ordering.is_gt(),
+                        _ => ordering.is_ge(),
+                    }));
+                }
+            };
+            Quantity {
+                value,
+                unit: lhs.unit,
+            }
+        }
+    };
+    result.into_value(span)
+}
+
+/// A quantity raised to an integer power, which also raises its unit.
+fn power(lhs: Value, rhs: Value, span: Span) -> Result<Value, EvalError> {
+    let exponent = rhs
+        .expect_integer(span)?
+        .to_bigint()
+        .to_i32()
+        .ok_or(EvalError::Overflow { span })?;
+    let lhs = quantity(lhs, span)?;
+    Quantity {
+        value: lhs.value.powi(exponent),
+        unit: lhs.unit.pow(exponent).ok_or(EvalError::Overflow { span })?,
+    }
+    .into_value(span)
+}
+
+/// `value to unit`, which re-expresses a quantity in a unit of the same
+/// dimension.
+pub(crate) fn convert(value: Value, unit: &Unit, span: Span) -> Result<Value, EvalError> {
+    let quantity = quantity(value, span)?;
+    if quantity.unit.dimension() != unit.dimension() {
+        return Err(mismatch(&quantity.unit, unit, span));
+    }
+    Ok(Value::Quantity(Quantity {
+        value: quantity.value_in(unit),
+        unit: unit.clone(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(terms: &[(&str, i32)]) -> Unit {
+        terms
+            .iter()
+            .fold(Unit::default(), |unit, (name, exponent)| {
+                let named = Unit::named(name).unwrap().pow(*exponent).unwrap();
+                unit.mul(&named).unwrap()
+            })
+    }
+
+    #[test]
+    fn unit_algebra() {
+        let test_table = vec![
+            (unit(&[("m", 1), ("s", -1)]), "m/s"),
+            (unit(&[("kg", 1), ("m", 2), ("s", -2)]), "kg*m^2/s^2"),
+            (unit(&[("s", -1)]), "1/s"),
+            (unit(&[("m", 1), ("km", 1)]), "m*km"),
+            (unit(&[("m", 2), ("m", -1)]), "m"),
+        ];
+        for (unit, expected) in test_table.into_iter() {
+            assert_eq!(unit.to_string(), expected);
+        }
+
+        let speed = unit(&[("km", 1), ("h", -1)]);
+        assert_eq!(speed.dimension(), unit(&[("mph", 1)]).dimension());
+        assert!((speed.factor() - 1.0 / 3.6).abs() < 1e-15);
+        assert_eq!(unit(&[("m", 1), ("m", -1)]), Unit::default());
+        assert_eq!(Unit::named("furlong"), None);
+    }
+}
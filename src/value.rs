@@ -0,0 +1,673 @@
+use crate::env::check_interrupt;
+use crate::error::{EvalError, Span};
+use crate::matrix::{self, Matrix};
+use crate::units::{self, Quantity};
+use crate::{complex, IntType, NumberFormat, Op, OverflowMode};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Largest integer result, in bits, that `^` will compute exactly before
+/// reporting an overflow.
+pub(crate) const MAX_POWER_BITS: u64 = 1 << 20;
+
+/// Largest `n` for which `n!` is computed; 20000! has about 77000 digits.
+const MAX_FACTORIAL: u64 = 20_000;
+
+/// Most elements a range like `1..n` may produce.
+pub(crate) const MAX_RANGE_LEN: u64 = 1 << 20;
+
+/// The result of evaluating an `Expr`.
+///
+/// Arithmetic on integers and rationals stays exact: an inexact division or
+/// a negative power gives a `Rational`, and only a real operand or a
+/// non-integer power promotes to `Real`. Integers that don't fit in an
+/// `i64` are held as `BigInt`, and results that fit again drop back to
+/// `Integer`, just as rationals with a denominator of one do.
+///
+/// `Fixed` integers instead keep their type through arithmetic with other
+/// integers, and results outside its range wrap or fail according to the
+/// environment's `OverflowMode`.
+///
+/// `Complex` values always have a nonzero imaginary part; results without
+/// one are held as `Real`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    BigInt(BigInt),
+    Rational(BigRational),
+    Real(f64),
+    Bool(bool),
+    /// A value of a fixed-width integer type, always within its range.
+    Fixed(i128, IntType),
+    /// A number with a unit of measure, like `3 m/s`.
+    Quantity(Quantity),
+    /// A number with an imaginary part, like `3 + 2i`.
+    Complex(Complex64),
+    Matrix(Matrix),
+}
+
+/// The operators that work on numbers alone.
+#[derive(Debug, Clone, Copy)]
+enum Arith {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+}
+
+/// The operators that work on integers alone.
+#[derive(Debug, Clone, Copy)]
+enum Bitwise {
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl Value {
+    /// Applies a binary operator, reporting failures against `span`.
+    pub(crate) fn binary_op(
+        self,
+        op: &Op,
+        rhs: Value,
+        mode: OverflowMode,
+        span: Span,
+    ) -> Result<Value, EvalError> {
+        if let Op::Range = op {
+            return self.range(rhs, span);
+        }
+        if let (Value::Quantity(_), _) | (_, Value::Quantity(_)) = (&self, &rhs) {
+            return units::binary_op(self, op, rhs, span);
+        }
+        if let (Value::Matrix(_), _) | (_, Value::Matrix(_)) = (&self, &rhs) {
+            return matrix::binary_op(self, op, rhs, mode, span);
+        }
+        if let (Value::Complex(_), _) | (_, Value::Complex(_)) = (&self, &rhs) {
+            return complex::binary_op(self, op, rhs, span);
+        }
+        let op = match op {
+            Op::Add => Arith::Add,
+            Op::Subtract => Arith::Subtract,
+            Op::Multiply => Arith::Multiply,
+            Op::Divide => Arith::Divide,
+            Op::Modulo => Arith::Modulo,
+            Op::Power => Arith::Power,
+            Op::BitAnd => return self.bitwise_op(Bitwise::And, rhs, mode, span),
+            Op::BitOr => return self.bitwise_op(Bitwise::Or, rhs, mode, span),
+            Op::BitXor => return self.bitwise_op(Bitwise::Xor, rhs, mode, span),
+            Op::ShiftLeft => return self.bitwise_op(Bitwise::ShiftLeft, rhs, mode, span),
+            Op::ShiftRight => return self.bitwise_op(Bitwise::ShiftRight, rhs, mode, span),
+            Op::Equal | Op::NotEqual => {
+                let equal = match (&self, &rhs) {
+                    (Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
+                    _ => self
+                        .expect_number(span)?
+                        .cmp_numeric(rhs.expect_number(span)?)
+                        .is_eq(),
+                };
+                return Ok(Value::Bool(equal == matches!(op, Op::Equal)));
+            }
+            Op::Range => unreachable!("ranges are built above"),
+            Op::Less | Op::LessEqual | Op::Greater | Op::GreaterEqual => {
+                let ordering = self
+                    .expect_number(span)?
+                    .cmp_numeric(rhs.expect_number(span)?);
+                return Ok(Value::Bool(match op {
+                    Op::Less => ordering.is_lt(),
+                    Op::LessEqual => ordering.is_le(),
+                    Op::Greater => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                }));
+            }
+            Op::And => return Ok(Value::Bool(self.as_bool(span)? && rhs.as_bool(span)?)),
+            Op::Or => return Ok(Value::Bool(self.as_bool(span)? || rhs.as_bool(span)?)),
+        };
+        self.expect_number(span)?;
+        rhs.expect_number(span)?;
+        match (self, rhs) {
+            (Value::Fixed(lhs, ty), rhs) if rhs.is_integer() => {
+                fixed_op(lhs, op, &rhs, ty, mode, span)
+            }
+            // The exponent doesn't take on the base's type, so a plain base
+            // stays plain
+            (lhs, Value::Fixed(rhs, ty)) if lhs.is_integer() && !matches!(op, Arith::Power) => {
+                let lhs = lhs.to_fixed(ty, mode, span)?;
+                fixed_op(lhs, op, &Value::Fixed(rhs, ty), ty, mode, span)
+            }
+            (Value::Integer(lhs), Value::Integer(rhs)) => integer_op(lhs, op, rhs, span),
+            (Value::Real(lhs), rhs) => real_op(lhs, op, rhs.to_f64(), span),
+            (lhs, Value::Real(rhs)) => real_op(lhs.to_f64(), op, rhs, span),
+            (Value::Rational(lhs), rhs) => rational_op(lhs, op, rhs.to_rational(), span),
+            (lhs, Value::Rational(rhs)) => rational_op(lhs.to_rational(), op, rhs, span),
+            (lhs, rhs) => big_op(lhs.to_bigint(), op, rhs.to_bigint(), span),
+        }
+    }
+
+    /// Applies `&`, `|`, `xor`, `<<` or `>>`. Plain integers behave as if
+    /// they had infinitely many bits, in two's complement.
+    fn bitwise_op(
+        self,
+        op: Bitwise,
+        rhs: Value,
+        mode: OverflowMode,
+        span: Span,
+    ) -> Result<Value, EvalError> {
+        self.expect_integer(span)?;
+        rhs.expect_integer(span)?;
+        // As with the exponent of a power, a shift amount doesn't take on
+        // the type of the value being shifted
+        let ty = match (&self, &rhs) {
+            (Value::Fixed(_, ty), _) => Some(*ty),
+            (_, Value::Fixed(_, ty)) if !matches!(op, Bitwise::ShiftLeft | Bitwise::ShiftRight) => {
+                Some(*ty)
+            }
+            _ => None,
+        };
+        let operand = |value: &Value| match ty {
+            Some(ty) => value.to_fixed(ty, mode, span).map(BigInt::from),
+            None => Ok(value.to_bigint()),
+        };
+        let lhs = operand(&self)?;
+        let result = match op {
+            Bitwise::And => lhs & operand(&rhs)?,
+            Bitwise::Or => lhs | operand(&rhs)?,
+            Bitwise::Xor => lhs ^ operand(&rhs)?,
+            Bitwise::ShiftLeft => shift(lhs, true, &rhs, ty, span)?,
+            Bitwise::ShiftRight => shift(lhs, false, &rhs, ty, span)?,
+        };
+        Ok(match ty {
+            Some(ty) => Value::Fixed(ty.fit(result, mode, span)?, ty),
+            None => Value::from(result),
+        })
+    }
+
+    /// The integers from `self` to `end` as a row vector, counting down if
+    /// `end` is smaller.
+    fn range(self, end: Value, span: Span) -> Result<Value, EvalError> {
+        let start = self.expect_integer(span)?.to_bigint();
+        let end = end.expect_integer(span)?.to_bigint();
+        let len = match (&end - &start).magnitude().to_u64() {
+            Some(len) if len < MAX_RANGE_LEN => len + 1,
+            _ => return Err(EvalError::Overflow { span }),
+        };
+        let step = BigInt::from(if end < start { -1 } else { 1 });
+        let elements = (0..len)
+            .map(|i| {
+                check_interrupt(span)?;
+                Ok(Value::from(&start + &step * i))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Value::Matrix(Matrix::new(1, len as usize, elements)))
+    }
+
+    /// `n!` for a non-negative integer `n`.
+    pub(crate) fn factorial(self, mode: OverflowMode, span: Span) -> Result<Value, EvalError> {
+        let n = self.expect_integer(span)?.to_bigint();
+        if n.is_negative() {
+            return Err(EvalError::InvalidArgument {
+                name: "factorial".to_string(),
+                reason: "expected a non-negative integer".to_string(),
+                span,
+            });
+        }
+        let n = match n.to_u64() {
+            Some(n) if n <= MAX_FACTORIAL => n,
+            _ => return Err(EvalError::Overflow { span }),
+        };
+        let result = (2..=n).try_fold(BigInt::from(1), |acc, i| {
+            check_interrupt(span)?;
+            Ok(acc * i)
+        })?;
+        Ok(match self {
+            Value::Fixed(_, ty) => Value::Fixed(ty.fit(result, mode, span)?, ty),
+            _ => Value::from(result),
+        })
+    }
+
+    /// Bitwise complement, `~x`.
+    pub(crate) fn bit_not(self, span: Span) -> Result<Value, EvalError> {
+        self.expect_integer(span)?;
+        Ok(match self {
+            Value::Integer(val) => Value::Integer(!val),
+            // Keeps the type's own bits of the infinitely wide complement
+            Value::Fixed(val, ty) => Value::Fixed(ty.wrap(&!BigInt::from(val)), ty),
+            val => Value::from(!val.to_bigint()),
+        })
+    }
+
+    /// Converts an integer to `ty`, for arithmetic with a value of that
+    /// type. Values of another fixed-width type are rejected rather than
+    /// silently converted.
+    fn to_fixed(&self, ty: IntType, mode: OverflowMode, span: Span) -> Result<i128, EvalError> {
+        match self {
+            Value::Fixed(val, other) if *other == ty => Ok(*val),
+            Value::Fixed(_, other) => Err(EvalError::TypeMismatch {
+                expected: ty.name(),
+                found: other.name(),
+                span,
+            }),
+            val => ty.fit(val.to_bigint(), mode, span),
+        }
+    }
+
+    pub(crate) fn negate(self, mode: OverflowMode, span: Span) -> Result<Value, EvalError> {
+        match self {
+            Value::Integer(val) => Ok(match val.checked_neg() {
+                Some(val) => Value::Integer(val),
+                None => Value::from(-BigInt::from(val)),
+            }),
+            Value::BigInt(val) => Ok(Value::from(-val)),
+            Value::Rational(val) => Ok(Value::from(-val)),
+            Value::Real(val) => real(-val, span),
+            Value::Fixed(val, ty) => Ok(Value::Fixed(ty.fit(-BigInt::from(val), mode, span)?, ty)),
+            Value::Quantity(quantity) => Ok(Value::Quantity(Quantity {
+                value: -quantity.value,
+                ..quantity
+            })),
+            Value::Complex(val) => complex::complex(-val, span),
+            Value::Matrix(val) => Ok(Value::Matrix(val.map(|val| val.negate(mode, span))?)),
+            Value::Bool(_) => Err(self.type_mismatch("a number", span)),
+        }
+    }
+
+    /// A short description of the kind of value, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Integer(_)
+            | Value::BigInt(_)
+            | Value::Rational(_)
+            | Value::Real(_)
+            | Value::Fixed(..)
+            | Value::Complex(_) => "a number",
+            Value::Bool(_) => "a boolean",
+            Value::Quantity(_) => "a quantity",
+            Value::Matrix(_) => "a matrix",
+        }
+    }
+
+    /// Whether the value is a plain number, with no unit.
+    pub fn is_number(&self) -> bool {
+        !matches!(self, Value::Bool(_) | Value::Quantity(_) | Value::Matrix(_))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Value::Integer(_) | Value::BigInt(_) | Value::Fixed(..)
+        )
+    }
+
+    pub(crate) fn as_bool(&self, span: Span) -> Result<bool, EvalError> {
+        match self {
+            Value::Bool(val) => Ok(*val),
+            _ => Err(self.type_mismatch("a boolean", span)),
+        }
+    }
+
+    pub(crate) fn expect_number(&self, span: Span) -> Result<&Value, EvalError> {
+        if self.is_number() {
+            Ok(self)
+        } else {
+            Err(self.type_mismatch("a number", span))
+        }
+    }
+
+    pub(crate) fn expect_integer(&self, span: Span) -> Result<&Value, EvalError> {
+        let found = match self {
+            _ if self.is_integer() => return Ok(self),
+            Value::Rational(_) => "a fraction",
+            Value::Real(_) => "a real number",
+            Value::Complex(_) => "a complex number",
+            _ => self.kind(),
+        };
+        Err(EvalError::TypeMismatch {
+            expected: "an integer",
+            found,
+            span,
+        })
+    }
+
+    /// Checks for a number without an imaginary part.
+    pub(crate) fn expect_real(&self, span: Span) -> Result<&Value, EvalError> {
+        match self.expect_number(span)? {
+            Value::Complex(_) => Err(EvalError::TypeMismatch {
+                expected: "a real number",
+                found: "a complex number",
+                span,
+            }),
+            val => Ok(val),
+        }
+    }
+
+    pub(crate) fn expect_matrix(&self, span: Span) -> Result<&Matrix, EvalError> {
+        match self {
+            Value::Matrix(val) => Ok(val),
+            _ => Err(self.type_mismatch("a matrix", span)),
+        }
+    }
+
+    pub(crate) fn type_mismatch(&self, expected: &'static str, span: Span) -> EvalError {
+        EvalError::TypeMismatch {
+            expected,
+            found: self.kind(),
+            span,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Value::Integer(val) => *val as f64,
+            Value::BigInt(val) => to_f64(val),
+            Value::Rational(val) => ratio_to_f64(val),
+            Value::Real(val) => *val,
+            Value::Bool(_) | Value::Quantity(_) | Value::Complex(_) | Value::Matrix(_) => f64::NAN,
+            Value::Fixed(val, _) => *val as f64,
+        }
+    }
+
+    pub(crate) fn to_complex(&self) -> Complex64 {
+        match self {
+            Value::Complex(val) => *val,
+            val => Complex64::new(val.to_f64(), 0.0),
+        }
+    }
+
+    /// Whether the value is a number held exactly, i.e. isn't a `Real` or
+    /// `Complex`.
+    pub fn is_exact(&self) -> bool {
+        matches!(
+            self,
+            Value::Integer(_) | Value::BigInt(_) | Value::Rational(_) | Value::Fixed(..)
+        )
+    }
+
+    /// Orders two numbers by value, exactly unless either one is a `Real`.
+    pub(crate) fn cmp_numeric(&self, other: &Value) -> Ordering {
+        if self.is_exact() && other.is_exact() {
+            self.to_rational().cmp(&other.to_rational())
+        } else {
+            // Adding zero turns -0.0 into 0.0, which `total_cmp` orders
+            // below it
+            (self.to_f64() + 0.0).total_cmp(&(other.to_f64() + 0.0))
+        }
+    }
+
+    /// Converts an exact value to a `BigInt`. Fractions and reals are
+    /// truncated, and booleans become 0 or 1.
+    pub(crate) fn to_bigint(&self) -> BigInt {
+        match self {
+            Value::Integer(val) => BigInt::from(*val),
+            Value::BigInt(val) => val.clone(),
+            Value::Rational(val) => val.to_integer(),
+            Value::Real(val) => BigInt::from(*val as i64),
+            Value::Bool(val) => BigInt::from(*val as i64),
+            Value::Fixed(val, _) => BigInt::from(*val),
+            Value::Quantity(quantity) => BigInt::from(quantity.value as i64),
+            Value::Complex(val) => BigInt::from(val.re as i64),
+            Value::Matrix(_) => BigInt::from(0),
+        }
+    }
+
+    /// Converts an exact value to a `BigRational`. Reals are truncated.
+    pub(crate) fn to_rational(&self) -> BigRational {
+        match self {
+            Value::Rational(val) => val.clone(),
+            val => BigRational::from_integer(val.to_bigint()),
+        }
+    }
+}
+
+impl From<BigRational> for Value {
+    fn from(val: BigRational) -> Value {
+        if val.is_integer() {
+            Value::from(val.to_integer())
+        } else {
+            Value::Rational(val)
+        }
+    }
+}
+
+impl From<BigInt> for Value {
+    fn from(val: BigInt) -> Value {
+        match val.to_i64() {
+            Some(val) => Value::Integer(val),
+            None => Value::BigInt(val),
+        }
+    }
+}
+
+/// Arithmetic on a fixed-width value. Division truncates, as it does for
+/// machine integers, rather than giving a fraction.
+fn fixed_op(
+    lhs: i128,
+    op: Arith,
+    rhs: &Value,
+    ty: IntType,
+    mode: OverflowMode,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let lhs = BigInt::from(lhs);
+    let operand = || rhs.to_fixed(ty, mode, span).map(BigInt::from);
+    let result = match op {
+        Arith::Add => lhs + operand()?,
+        Arith::Subtract => lhs - operand()?,
+        Arith::Multiply => lhs * operand()?,
+        Arith::Divide | Arith::Modulo => {
+            let rhs = operand()?;
+            if rhs.is_zero() {
+                return Err(EvalError::DivisionByZero { span });
+            }
+            if let Arith::Divide = op {
+                lhs / rhs
+            } else {
+                lhs % rhs
+            }
+        }
+        Arith::Power => {
+            let exponent = rhs.to_bigint();
+            if exponent.is_negative() {
+                return Err(EvalError::NegativeExponent { span });
+            }
+            match mode {
+                // Only the low bits are kept, so they're all that's computed
+                OverflowMode::Wrapping => lhs.modpow(&exponent, &(BigInt::from(1) << ty.bits())),
+                OverflowMode::Checked => big_pow(lhs, &exponent, span)?,
+            }
+        }
+    };
+    Ok(Value::Fixed(ty.fit(result, mode, span)?, ty))
+}
+
+/// Shifts `value` by `amount` bits, to the left or right. `ty` is the type
+/// of a fixed-width value, whose range the caller brings the result into.
+fn shift(
+    value: BigInt,
+    left: bool,
+    amount: &Value,
+    ty: Option<IntType>,
+    span: Span,
+) -> Result<BigInt, EvalError> {
+    let amount = amount.to_bigint();
+    if amount.is_negative() {
+        return Err(EvalError::NegativeShift { span });
+    }
+    let amount = amount.to_u64().unwrap_or(u64::MAX);
+    // Shifting right by the value's length, or a fixed-width value by its
+    // width, leaves nothing more to shift out
+    let limit = ty.map_or(value.bits(), |ty| u64::from(ty.bits()));
+    if !left {
+        return Ok(value >> amount.min(limit));
+    }
+    match ty {
+        Some(_) => Ok(value << amount.min(limit)),
+        None if value.is_zero() => Ok(value),
+        None if value.bits().saturating_add(amount) > MAX_POWER_BITS => {
+            Err(EvalError::Overflow { span })
+        }
+        None => Ok(value << amount),
+    }
+}
+
+fn integer_op(lhs: i64, op: Arith, rhs: i64, span: Span) -> Result<Value, EvalError> {
+    let result = match op {
+        Arith::Add => lhs.checked_add(rhs),
+        Arith::Subtract => lhs.checked_sub(rhs),
+        Arith::Multiply => lhs.checked_mul(rhs),
+        // Division, modulo and power have edge cases that big_op handles
+        Arith::Divide | Arith::Modulo | Arith::Power => None,
+    };
+    match result {
+        Some(val) => Ok(Value::Integer(val)),
+        None => big_op(BigInt::from(lhs), op, BigInt::from(rhs), span),
+    }
+}
+
+fn big_op(lhs: BigInt, op: Arith, rhs: BigInt, span: Span) -> Result<Value, EvalError> {
+    let result = match op {
+        Arith::Add => lhs + rhs,
+        Arith::Subtract => lhs - rhs,
+        Arith::Multiply => lhs * rhs,
+        Arith::Divide | Arith::Modulo if rhs.is_zero() => {
+            return Err(EvalError::DivisionByZero { span });
+        }
+        // Inexact division and negative powers produce fractions
+        Arith::Divide if !(&lhs % &rhs).is_zero() => {
+            return rational_op(lhs.into(), op, rhs.into(), span);
+        }
+        Arith::Divide => lhs / rhs,
+        Arith::Modulo => lhs % rhs,
+        Arith::Power if rhs.is_negative() => {
+            return rational_op(lhs.into(), op, rhs.into(), span);
+        }
+        Arith::Power => big_pow(lhs, &rhs, span)?,
+    };
+    Ok(Value::from(result))
+}
+
+fn rational_op(
+    lhs: BigRational,
+    op: Arith,
+    rhs: BigRational,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let result = match op {
+        Arith::Add => lhs + rhs,
+        Arith::Subtract => lhs - rhs,
+        Arith::Multiply => lhs * rhs,
+        Arith::Divide | Arith::Modulo if rhs.is_zero() => {
+            return Err(EvalError::DivisionByZero { span });
+        }
+        Arith::Divide => lhs / rhs,
+        Arith::Modulo => lhs % rhs,
+        Arith::Power if rhs.is_integer() => rational_pow(lhs, &rhs.to_integer(), span)?,
+        Arith::Power => return real_op(ratio_to_f64(&lhs), op, ratio_to_f64(&rhs), span),
+    };
+    Ok(Value::from(result))
+}
+
+fn rational_pow(
+    base: BigRational,
+    exponent: &BigInt,
+    span: Span,
+) -> Result<BigRational, EvalError> {
+    if base.is_zero() && exponent.is_negative() {
+        return Err(EvalError::DivisionByZero { span });
+    }
+    let magnitude = exponent.abs();
+    let (numer, denom) = base.into();
+    let result = BigRational::new(
+        big_pow(numer, &magnitude, span)?,
+        big_pow(denom, &magnitude, span)?,
+    );
+    Ok(if exponent.is_negative() {
+        result.recip()
+    } else {
+        result
+    })
+}
+
+fn big_pow(base: BigInt, exponent: &BigInt, span: Span) -> Result<BigInt, EvalError> {
+    // Bases 0, 1 and -1 never grow, whatever the exponent
+    if base.magnitude().bits() <= 1 {
+        let even = !exponent.bit(0);
+        return Ok(if exponent.is_zero() || (base.is_negative() && even) {
+            BigInt::from(1)
+        } else {
+            base
+        });
+    }
+    match exponent.to_u32() {
+        Some(exponent) if base.bits() * u64::from(exponent) <= MAX_POWER_BITS => {
+            Ok(base.pow(exponent))
+        }
+        _ => Err(EvalError::Overflow { span }),
+    }
+}
+
+pub(crate) fn to_f64(val: &BigInt) -> f64 {
+    val.to_f64().unwrap_or(f64::INFINITY)
+}
+
+fn ratio_to_f64(val: &BigRational) -> f64 {
+    val.to_f64().unwrap_or(f64::NAN)
+}
+
+fn real_op(lhs: f64, op: Arith, rhs: f64, span: Span) -> Result<Value, EvalError> {
+    let result = match op {
+        Arith::Add => lhs + rhs,
+        Arith::Subtract => lhs - rhs,
+        Arith::Multiply => lhs * rhs,
+        Arith::Divide | Arith::Modulo if rhs == 0.0 => {
+            return Err(EvalError::DivisionByZero { span });
+        }
+        Arith::Divide => lhs / rhs,
+        Arith::Modulo => lhs % rhs,
+        Arith::Power if lhs == 0.0 && rhs < 0.0 => {
+            return Err(EvalError::DivisionByZero { span });
+        }
+        // A negative number has no real roots, so `(-4)^0.5` is `2i`
+        Arith::Power if lhs < 0.0 && rhs.fract() != 0.0 => {
+            let result = complex::power(Complex64::new(lhs, 0.0), &Value::Real(rhs));
+            return complex::complex(result, span);
+        }
+        Arith::Power => lhs.powf(rhs),
+    };
+    real(result, span)
+}
+
+/// Wraps a computed float, rejecting results that aren't finite reals.
+pub(crate) fn real(val: f64, span: Span) -> Result<Value, EvalError> {
+    if val.is_nan() {
+        Err(EvalError::Domain { span })
+    } else if val.is_infinite() {
+        Err(EvalError::Overflow { span })
+    } else {
+        Ok(Value::Real(val))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(val) => write!(f, "{}", val),
+            Value::BigInt(val) => write!(f, "{}", val),
+            Value::Rational(val) => write!(f, "{}", val),
+            Value::Real(val) if *val != 0.0 && (val.abs() >= 1e16 || val.abs() < 1e-9) => {
+                write!(f, "{:e}", val)
+            }
+            Value::Real(val) => write!(f, "{}", val),
+            Value::Bool(val) => write!(f, "{}", val),
+            Value::Fixed(val, _) => write!(f, "{}", val),
+            Value::Quantity(quantity) => write!(f, "{}", quantity),
+            Value::Complex(val) => write!(f, "{}", complex::format(val, NumberFormat::Fraction)),
+            Value::Matrix(val) => write!(f, "{}", val),
+        }
+    }
+}